@@ -0,0 +1,131 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Baseline timings of the hot paths of the server. Run them with
+//! `cargo bench`. They use a tiny harness of their own so that no
+//! additional dependencies are needed.
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use tcpmt::{Operation, TlvIterator};
+
+/// Size of the buffer the server reads into
+const BUFFER_LEN: usize = 2048;
+
+/// Runs `f` repeatedly for about a second and prints the time per iteration
+fn bench(name: &str, mut f: impl FnMut()) {
+    // Warm up and estimate how many iterations fit in the time budget
+    let mut iterations = 1u64;
+    loop {
+        let start = Instant::now();
+        for _ in 0..iterations {
+            f();
+        }
+        if start.elapsed() > Duration::from_millis(100) {
+            break;
+        }
+        iterations *= 2;
+    }
+
+    iterations *= 10;
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{name:<24} {:>12.1} ns/iter ({iterations} iterations)",
+        elapsed.as_nanos() as f64 / iterations as f64
+    );
+}
+
+fn operations() -> Vec<Operation> {
+    [
+        "100+27",
+        "-100-27",
+        "11*11",
+        "-128/3",
+        "127%10",
+        "20!",
+        "min(3,9)",
+        "max(3,9)",
+        "12&10",
+        "12|10",
+        "12^^10",
+        "3<<4",
+        "-128>>3",
+        "sqrt(127)",
+        "log2(127)",
+        "modpow(7,127,127)",
+        "absdiff(-128,127)",
+        "200u+100u",
+        "200u*100u",
+    ]
+    .iter()
+    .map(|s| s.parse().unwrap())
+    .collect()
+}
+
+fn main() {
+    let operations = operations();
+
+    bench("reduce", || {
+        for operation in &operations {
+            let _ = black_box(black_box(operation).reduce());
+        }
+    });
+
+    bench("encode", || {
+        for operation in &operations {
+            black_box(black_box(operation).clone().encode());
+        }
+    });
+
+    let mut buffer = Vec::with_capacity(BUFFER_LEN);
+    bench("write_to", || {
+        buffer.clear();
+        for operation in &operations {
+            black_box(operation).write_to(&mut buffer).unwrap();
+        }
+        black_box(&buffer);
+    });
+
+    let mut packed = Vec::with_capacity(BUFFER_LEN);
+    for operation in operations.iter().cycle() {
+        let encoded = operation.clone().encode();
+        if packed.len() + encoded.len() > BUFFER_LEN {
+            break;
+        }
+        packed.extend_from_slice(&encoded);
+    }
+    bench("iterate 2048 bytes", || {
+        black_box(TlvIterator::process(black_box(&packed)).count());
+    });
+    bench("decode 2048 bytes", || {
+        for operation in TlvIterator::process(black_box(&packed)).operations() {
+            let _ = black_box(operation);
+        }
+    });
+}
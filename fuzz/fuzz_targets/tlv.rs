@@ -0,0 +1,51 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Feeds arbitrary bytes to the TLV parser. Run it from the root of the
+//! repository with a nightly toolchain and `cargo-fuzz` installed:
+//!
+//! ```sh
+//! cargo +nightly fuzz run tlv
+//! ```
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tcpmt::{hexdump_annotated, Answer, Frame, Operation, Tlv, TlvIterator};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(tlv) = Tlv::try_from(data) {
+        assert_eq!(tlv.data.len(), tlv.length as usize);
+        let _ = Operation::try_from(tlv);
+    }
+
+    // Every TLV takes at least two bytes, so the iterator must stop soon
+    let mut consumed = 0;
+    for tlv in TlvIterator::process(data) {
+        consumed += 2 + tlv.data.len();
+        assert!(consumed <= data.len());
+        let _ = Answer::try_from(tlv);
+    }
+
+    let _ = Frame::try_from(data);
+    let _ = hexdump_annotated(data);
+});
@@ -20,38 +20,188 @@
  *
  */
 
-use crate::{tlv::TlvType, TCPLibError, Tlv, TlvIterator};
+use crate::{
+    tlv::{TlvBuilder, TlvError, TlvType},
+    TCPLibError, Tlv,
+};
 use std::{fmt::Display, str};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 pub enum AnswerOrder {
+    #[default]
     MessageFirst,
     MessageLast,
 }
 
-#[derive(Debug)]
+/// The answer to one or several operations. When answering a batch,
+/// `partials` holds the accumulator after each operation but the last one,
+/// which is stored in `acc`. They are encoded in that order, and the
+/// message, if any, goes before or after all of them depending on
+/// [`AnswerOrder`]. The optional `delta` is the change of the accumulator
+/// caused by the last operation, and `result` is the result of that
+/// operation on its own. `float` is the result of a floating-point
+/// operation, which leaves the accumulator untouched. They are encoded in
+/// that order just before the numbers.
+/// Any other child TLVs received are kept, as tag and data, in `extra` and
+/// encoded last, so that decoding and encoding an answer preserves them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
 pub struct Answer {
     pub acc: Numberi64,
+    pub partials: Vec<Numberi64>,
     pub message: Option<InvalidOperation>,
+    pub delta: Option<Numberi64>,
+    pub result: Option<Numberi64>,
+    pub float: Option<Numberf64>,
+    pub extra: Vec<(u8, Box<[u8]>)>,
 }
 
 impl Answer {
-    pub fn encode(self, order: AnswerOrder) -> Box<[u8]> {
-        let message = self.message.map_or(vec![], |v| v.encode().to_vec());
-        let data = match order {
-            AnswerOrder::MessageFirst => {
-                let mut data = message;
-                data.extend_from_slice(&self.acc.encode());
-                data
+    /// Most accumulator values that fit in an answer
+    pub const MAX_VALUES: usize = u8::MAX as usize / (2 + Numberi64::WIRE_LEN);
+
+    /// A successful answer
+    pub fn ok(acc: i64) -> Self {
+        Answer {
+            acc: acc.into(),
+            ..Default::default()
+        }
+    }
+
+    /// An answer reporting that the last operation failed
+    pub fn error(acc: i64, message: impl Into<String>) -> Self {
+        Answer {
+            acc: acc.into(),
+            message: Some(message.into().into()),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the change of the accumulator caused by the last operation
+    pub fn with_delta(self, delta: i64) -> Self {
+        Answer {
+            delta: Some(delta.into()),
+            ..self
+        }
+    }
+
+    /// Sets the result of the last operation, before adding it to the
+    /// accumulator
+    pub fn with_result(self, result: i64) -> Self {
+        Answer {
+            result: Some(result.into()),
+            ..self
+        }
+    }
+
+    /// Sets the result of a floating-point operation
+    pub fn with_float(self, result: f64) -> Self {
+        Answer {
+            float: Some(result.into()),
+            ..self
+        }
+    }
+
+    /// All the accumulator values in the answer, the final one last
+    pub fn values(&self) -> impl Iterator<Item = &Numberi64> {
+        self.partials.iter().chain([&self.acc])
+    }
+
+    /// Length of the encoded children other than the message
+    fn children_len(&self) -> usize {
+        let numbers = self.delta.iter().chain(&self.result).chain(self.values());
+        numbers.count() * (2 + Numberi64::WIRE_LEN)
+            + self.float.map_or(0, |_| 2 + Numberf64::WIRE_LEN)
+            + self
+                .extra
+                .iter()
+                .map(|(_, data)| 2 + data.len())
+                .sum::<usize>()
+    }
+
+    /// Encodes the answer, truncating the message to the room left by the
+    /// other children. Fails if they do not fit in a TLV by themselves.
+    pub fn encode(self, order: AnswerOrder) -> Result<Box<[u8]>, TlvError> {
+        let room = (u8::MAX as usize).saturating_sub(self.children_len() + 2);
+        let message = self
+            .message
+            .as_ref()
+            .map(|message| truncate(&message.0, room))
+            .filter(|message| !message.is_empty());
+
+        let mut children = TlvBuilder::new();
+        if let (AnswerOrder::MessageFirst, Some(message)) = (order, message) {
+            children.push(TlvType::Invalid, message.as_bytes())?;
+        }
+        if let Some(delta) = &self.delta {
+            children.push(TlvType::Delta, &delta.0.to_be_bytes())?;
+        }
+        if let Some(result) = &self.result {
+            children.push(TlvType::OpResult, &result.0.to_be_bytes())?;
+        }
+        if let Some(float) = &self.float {
+            children.push(TlvType::Numf64, &float.0.to_be_bytes())?;
+        }
+        for value in self.values() {
+            children.push(TlvType::Numi64, &value.0.to_be_bytes())?;
+        }
+        if let (AnswerOrder::MessageLast, Some(message)) = (order, message) {
+            children.push(TlvType::Invalid, message.as_bytes())?;
+        }
+        for (tag, data) in &self.extra {
+            children.push_raw(*tag, data)?;
+        }
+
+        children.wrap(TlvType::Answer)
+    }
+
+    /// Like `try_from`, but failing if the message is not placed where
+    /// `expected` says.
+    pub fn try_from_ordered(tlv: Tlv, expected: AnswerOrder) -> Result<Self, TCPLibError> {
+        let mut number_seen = false;
+        let mut message_seen = false;
+        for tag in tlv
+            .raw_children()
+            .filter_map(|(tag, _)| TlvType::try_from(tag).ok())
+        {
+            match (tag, expected) {
+                (TlvType::Numi64, AnswerOrder::MessageLast) if message_seen => {
+                    return Err(TCPLibError::WrongAnswerOrder)
+                }
+                (TlvType::Invalid, AnswerOrder::MessageFirst) if number_seen => {
+                    return Err(TCPLibError::WrongAnswerOrder)
+                }
+                (TlvType::Numi64, _) => number_seen = true,
+                (TlvType::Invalid, _) => message_seen = true,
+                _ => {}
             }
-            AnswerOrder::MessageLast => {
-                let mut data = self.acc.encode().to_vec();
-                data.extend_from_slice(&message);
-                data
+        }
+
+        tlv.try_into()
+    }
+
+    /// Like `try_from`, but failing if there is more than one number or
+    /// message, as happens with batch answers.
+    pub fn try_from_strict(tlv: Tlv) -> Result<Self, TCPLibError> {
+        let (mut numbers, mut messages, mut deltas, mut results, mut floats) = (0, 0, 0, 0, 0);
+        for tag in tlv
+            .raw_children()
+            .filter_map(|(tag, _)| TlvType::try_from(tag).ok())
+        {
+            let count = match tag {
+                TlvType::Numi64 => &mut numbers,
+                TlvType::Invalid => &mut messages,
+                TlvType::Delta => &mut deltas,
+                TlvType::OpResult => &mut results,
+                TlvType::Numf64 => &mut floats,
+                _ => continue,
+            };
+            *count += 1;
+            if *count > 1 {
+                return Err(TCPLibError::DuplicateTlv(tag));
             }
-        };
+        }
 
-        Tlv::new(TlvType::Answer, &data).unwrap().encode()
+        tlv.try_into()
     }
 }
 
@@ -61,20 +211,50 @@ impl<'a> TryFrom<Tlv<'a>> for Answer {
     fn try_from(tlv: Tlv<'a>) -> Result<Self, Self::Error> {
         if tlv.tag == TlvType::Answer && tlv.length > 0 {
             let mut message: Option<InvalidOperation> = None;
-            let mut acc_tlv: Option<Numberi64> = None;
-            for ref tlv in TlvIterator::process(tlv.data) {
+            let mut partials: Vec<Numberi64> = vec![];
+            let mut delta = None;
+            let mut result = None;
+            let mut float = None;
+            let mut extra = vec![];
+            for (tag, data) in tlv.raw_children() {
+                let tlv = &match TlvType::try_from(tag) {
+                    Ok(tag) => Tlv::new(tag, data)?,
+                    Err(_) => {
+                        extra.push((tag, data.into()));
+                        continue;
+                    }
+                };
                 match tlv.tag {
-                    TlvType::Numi64 => acc_tlv = Some(tlv.try_into()?),
+                    TlvType::Numi64 => partials.push(tlv.try_into()?),
                     TlvType::Invalid => message = Some(tlv.try_into()?),
-                    _ => {} // Just ignore extra TLVs
+                    TlvType::Delta => {
+                        delta = Some(Numberi64(i64::from_be_bytes(
+                            tlv.fixed::<{ Numberi64::WIRE_LEN }>()?,
+                        )))
+                    }
+                    TlvType::OpResult => {
+                        result = Some(Numberi64(i64::from_be_bytes(
+                            tlv.fixed::<{ Numberi64::WIRE_LEN }>()?,
+                        )))
+                    }
+                    TlvType::Numf64 => float = Some(tlv.try_into()?),
+                    tag => extra.push((tag.into(), tlv.data.into())),
                 };
             }
 
-            if let Some(acc) = acc_tlv {
-                return Ok(Answer { acc, message });
+            if let Some(acc) = partials.pop() {
+                return Ok(Answer {
+                    acc,
+                    partials,
+                    message,
+                    delta,
+                    result,
+                    float,
+                    extra,
+                });
             }
         }
-        Err(TCPLibError::Generic)
+        Err(TCPLibError::Generic("Answer"))
     }
 }
 
@@ -83,21 +263,39 @@ impl From<(i64, Option<String>)> for Answer {
         Answer {
             acc: acc.into(),
             message: message.map(|m| m.into()),
+            ..Default::default()
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+impl TryFrom<(Vec<i64>, Option<String>)> for Answer {
+    type Error = TCPLibError;
+
+    /// Builds a batch answer, failing if there are no accumulator values
+    fn try_from((mut accs, message): (Vec<i64>, Option<String>)) -> Result<Self, Self::Error> {
+        let acc = accs.pop().ok_or(TCPLibError::EmptyAnswer)?;
+        Ok(Answer {
+            acc: acc.into(),
+            partials: accs.into_iter().map(Numberi64::from).collect(),
+            message: message.map(|m| m.into()),
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
 pub struct Numberi64(pub i64);
 
 impl<'a> TryFrom<&Tlv<'a>> for Numberi64 {
     type Error = TCPLibError;
 
     fn try_from(tlv: &Tlv) -> Result<Self, Self::Error> {
-        if tlv.tag == TlvType::Numi64 && tlv.length == 8 {
-            Ok(Numberi64(i64::from_be_bytes(tlv.data.try_into()?)))
+        if tlv.tag == TlvType::Numi64 {
+            Ok(Numberi64(i64::from_be_bytes(
+                tlv.fixed::<{ Self::WIRE_LEN }>()?,
+            )))
         } else {
-            Err(TCPLibError::Generic)
+            Err(TCPLibError::Generic("Numberi64"))
         }
     }
 }
@@ -111,11 +309,27 @@ impl<'a> TryFrom<Tlv<'a>> for Numberi64 {
 }
 
 impl Numberi64 {
+    /// Length of the encoded number
+    pub const WIRE_LEN: usize = 8;
+
     pub fn encode(self) -> Box<[u8]> {
         Tlv::new(TlvType::Numi64, &self.0.to_be_bytes())
             .unwrap()
             .encode()
     }
+
+    /// Decodes a big endian number of 1 to 8 bytes, extending its sign
+    pub fn from_trimmed_be(bytes: &[u8]) -> Result<Self, TCPLibError> {
+        match bytes {
+            [first, ..] if bytes.len() <= Self::WIRE_LEN => {
+                let fill = if *first as i8 >= 0 { 0 } else { 0xff };
+                let mut full = [fill; Self::WIRE_LEN];
+                full[Self::WIRE_LEN - bytes.len()..].copy_from_slice(bytes);
+                Ok(Numberi64(i64::from_be_bytes(full)))
+            }
+            _ => Err(TlvError::WrongFormat.into()),
+        }
+    }
 }
 
 impl From<i64> for Numberi64 {
@@ -130,7 +344,71 @@ impl Display for Numberi64 {
     }
 }
 
-#[derive(Debug, Clone)]
+/// A floating-point number. Numbers are compared and hashed by their bits,
+/// so that answers holding them can be kept in sets.
+#[derive(Debug, Clone, Copy)]
+pub struct Numberf64(pub f64);
+
+impl PartialEq for Numberf64 {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+
+impl Eq for Numberf64 {}
+
+impl std::hash::Hash for Numberf64 {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+impl<'a> TryFrom<&Tlv<'a>> for Numberf64 {
+    type Error = TCPLibError;
+
+    fn try_from(tlv: &Tlv) -> Result<Self, Self::Error> {
+        if tlv.tag == TlvType::Numf64 {
+            Ok(Numberf64(f64::from_be_bytes(
+                tlv.fixed::<{ Self::WIRE_LEN }>()?,
+            )))
+        } else {
+            Err(TCPLibError::Generic("Numberf64"))
+        }
+    }
+}
+
+impl<'a> TryFrom<Tlv<'a>> for Numberf64 {
+    type Error = TCPLibError;
+
+    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
+        (&tlv).try_into()
+    }
+}
+
+impl Numberf64 {
+    /// Length of the encoded number
+    pub const WIRE_LEN: usize = 8;
+
+    pub fn encode(self) -> Box<[u8]> {
+        Tlv::new(TlvType::Numf64, &self.0.to_be_bytes())
+            .unwrap()
+            .encode()
+    }
+}
+
+impl From<f64> for Numberf64 {
+    fn from(num: f64) -> Self {
+        Self(num)
+    }
+}
+
+impl Display for Numberf64 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct InvalidOperation(Box<str>);
 
 impl<'a> TryFrom<&Tlv<'a>> for InvalidOperation {
@@ -140,12 +418,22 @@ impl<'a> TryFrom<&Tlv<'a>> for InvalidOperation {
         if tlv.tag == TlvType::Invalid && tlv.length > 0 {
             Ok(InvalidOperation(str::from_utf8(tlv.data)?.into()))
         } else {
-            Err(TCPLibError::Generic)
+            Err(TCPLibError::Generic("InvalidOperation"))
         }
     }
 }
 
 impl InvalidOperation {
+    /// Longest message, in bytes, that still fits in an answer along with
+    /// the accumulator
+    pub const MAX_LEN: usize = u8::MAX as usize - 2 - (2 + Numberi64::WIRE_LEN);
+
+    /// Builds a message, truncating it to [`InvalidOperation::MAX_LEN`] bytes
+    /// without splitting any character
+    pub fn new(message: &str) -> Self {
+        Self(truncate(message, Self::MAX_LEN).into())
+    }
+
     pub fn encode(self) -> Box<[u8]> {
         Tlv::new(TlvType::Invalid, self.0.as_bytes())
             .unwrap()
@@ -153,12 +441,21 @@ impl InvalidOperation {
     }
 }
 
+/// The first `len` bytes of `s` at most, without splitting any character
+fn truncate(s: &str, len: usize) -> &str {
+    let mut len = s.len().min(len);
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+    &s[..len]
+}
+
 impl<T> From<T> for InvalidOperation
 where
     T: AsRef<str>,
 {
     fn from(message: T) -> Self {
-        Self(message.as_ref().into())
+        Self::new(message.as_ref())
     }
 }
 
@@ -170,7 +467,12 @@ impl Display for InvalidOperation {
 
 #[cfg(test)]
 mod tests {
-    use crate::{answer::Numberi64, Tlv};
+    use std::collections::HashSet;
+
+    use crate::{
+        answer::{InvalidOperation, Numberf64, Numberi64},
+        Answer, AnswerOrder, TCPLibError, Tlv, TlvType,
+    };
 
     #[test]
     fn parse_answer_1() {
@@ -199,6 +501,26 @@ mod tests {
         assert!(answer.is_err());
     }
 
+    #[test]
+    fn parse_trimmed() {
+        assert_eq!(Numberi64::from_trimmed_be(&[0x01]).unwrap(), 1.into());
+        assert_eq!(Numberi64::from_trimmed_be(&[0xff]).unwrap(), (-1).into());
+        assert_eq!(
+            Numberi64::from_trimmed_be(&[0x00, 0x80]).unwrap(),
+            128.into()
+        );
+        assert_eq!(
+            Numberi64::from_trimmed_be(&[0xff, 0x7f]).unwrap(),
+            (-129).into()
+        );
+        assert_eq!(
+            Numberi64::from_trimmed_be(&(-5i64).to_be_bytes()).unwrap(),
+            (-5).into()
+        );
+        assert!(Numberi64::from_trimmed_be(&[]).is_err());
+        assert!(Numberi64::from_trimmed_be(&[0; 9]).is_err());
+    }
+
     #[test]
     fn encode_answer() {
         assert_eq!(Numberi64(1).encode()[..], [16u8, 8, 0, 0, 0, 0, 0, 0, 0, 1]);
@@ -207,4 +529,274 @@ mod tests {
             [16u8, 8, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
         );
     }
+
+    #[test]
+    fn answer_constructors() {
+        let encoded = Answer::ok(5).encode(AnswerOrder::MessageFirst).unwrap();
+        assert_eq!(encoded[..], [10u8, 10, 16, 8, 0, 0, 0, 0, 0, 0, 0, 5]);
+        assert!(!encoded.contains(&11));
+
+        let encoded = Answer::error(5, "boom")
+            .encode(AnswerOrder::MessageFirst)
+            .unwrap();
+        assert_eq!(encoded[2..8], [11u8, 4, b'b', b'o', b'o', b'm']);
+
+        let answer = Answer::default();
+        assert_eq!(answer.acc.0, 0);
+        assert!(answer.partials.is_empty() && answer.message.is_none());
+    }
+
+    #[test]
+    fn answer_equality() {
+        assert_eq!(Answer::ok(5), Answer::ok(5));
+        assert_ne!(Answer::ok(5), Answer::ok(6));
+        assert_ne!(Answer::ok(5), Answer::error(5, "boom"));
+        assert_eq!(Answer::error(5, "boom"), Answer::error(5, "boom"));
+
+        let encoded = Answer::error(5, "boom")
+            .encode(AnswerOrder::MessageLast)
+            .unwrap();
+        let decoded = Answer::try_from(Tlv::try_from(&encoded[..]).unwrap()).unwrap();
+        assert_eq!(decoded, Answer::error(5, "boom"));
+
+        let answers: HashSet<_> = [Answer::ok(5), Answer::ok(5), Answer::ok(6)].into();
+        assert_eq!(answers.len(), 2);
+    }
+
+    #[test]
+    fn answer_children() {
+        let encoded = Answer::error(5, "x")
+            .encode(AnswerOrder::MessageFirst)
+            .unwrap();
+        let tlv = Tlv::try_from(&encoded[..]).unwrap();
+        let children: Vec<_> = tlv.children().collect();
+
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0].tag, TlvType::Invalid);
+        assert_eq!(children[0].data, b"x");
+        assert_eq!(children[1].tag, TlvType::Numi64);
+        assert_eq!(Numberi64::try_from(&children[1]).unwrap().0, 5);
+    }
+
+    #[test]
+    fn op_result() {
+        let answer = Answer::ok(107).with_result(7);
+        let encoded = answer.clone().encode(AnswerOrder::MessageFirst).unwrap();
+        let decoded = Answer::try_from_strict(Tlv::try_from(&encoded[..]).unwrap()).unwrap();
+        assert_eq!(decoded.acc.0, 107);
+        assert_eq!(decoded.result, Some(7.into()));
+        assert_eq!(decoded, answer);
+    }
+
+    #[test]
+    fn extra_children() {
+        let encoded = [10u8, 14, 16, 8, 0, 0, 0, 0, 0, 0, 0, 7, 20, 2, 0xab, 0xcd];
+        let answer = Answer::try_from(Tlv::try_from(&encoded[..]).unwrap()).unwrap();
+        assert_eq!(answer.acc.0, 7);
+        assert_eq!(answer.extra, [(20u8, vec![0xab, 0xcd].into())]);
+        assert_eq!(
+            answer.encode(AnswerOrder::MessageFirst).unwrap()[..],
+            encoded
+        );
+
+        // Tag 200 is not assigned, and the children after it are still read
+        let encoded = [
+            10u8, 20, 200, 3, 1, 2, 3, 16, 8, 0, 0, 0, 0, 0, 0, 0, 7, 250, 0, 11, 1, b'E',
+        ];
+        let answer = Answer::try_from(Tlv::try_from(&encoded[..]).unwrap()).unwrap();
+        assert_eq!(answer.acc.0, 7);
+        assert_eq!(answer.message.as_ref().unwrap().to_string(), "E");
+        assert_eq!(
+            answer.extra,
+            [(200u8, vec![1, 2, 3].into()), (250u8, vec![].into())]
+        );
+        let reencoded = answer.encode(AnswerOrder::MessageLast).unwrap();
+        let answer = Answer::try_from(Tlv::try_from(&reencoded[..]).unwrap()).unwrap();
+        assert_eq!(answer.extra.len(), 2);
+    }
+
+    #[test]
+    fn answer_clone() {
+        let answer = Answer::error(5, "x").with_delta(2);
+        let copy = answer.clone();
+        assert_eq!(copy, answer);
+        assert_eq!(copy.message.unwrap().to_string(), "x");
+    }
+
+    #[test]
+    fn long_message() {
+        let message = InvalidOperation::new(&"é".repeat(150));
+        assert_eq!(
+            message.to_string(),
+            "é".repeat(InvalidOperation::MAX_LEN / 2)
+        );
+        assert_eq!(message.clone().encode().len(), 2 + 242);
+
+        let encoded = Answer::error(5, "x".repeat(300))
+            .encode(AnswerOrder::MessageLast)
+            .unwrap();
+        assert_eq!(encoded.len(), 255 + 2);
+        let answer = Answer::try_from(Tlv::try_from(&encoded[..]).unwrap()).unwrap();
+        assert_eq!(answer.acc.0, 5);
+        assert_eq!(
+            answer.message.unwrap().to_string(),
+            "x".repeat(InvalidOperation::MAX_LEN)
+        );
+
+        // The message gives way to the other children
+        let mut answer = Answer::error(5, "x".repeat(300))
+            .with_delta(1)
+            .with_result(2)
+            .with_float(3.0);
+        answer.partials = vec![Numberi64(4); 3];
+        answer.extra = vec![(TlvType::Checksum.into(), [0, 0].into())];
+        for order in [AnswerOrder::MessageFirst, AnswerOrder::MessageLast] {
+            let encoded = answer.clone().encode(order).unwrap();
+            assert_eq!(encoded.len(), 255 + 2);
+            let decoded = Answer::try_from(Tlv::try_from(&encoded[..]).unwrap()).unwrap();
+            assert_eq!(decoded.message.unwrap().to_string(), "x".repeat(255 - 76));
+        }
+
+        answer.partials = vec![Numberi64(4); Answer::MAX_VALUES];
+        assert!(answer.encode(AnswerOrder::MessageFirst).is_err());
+    }
+
+    #[test]
+    fn wire_lengths() {
+        assert_eq!(Numberi64(1).encode().len(), 2 + Numberi64::WIRE_LEN);
+        assert_eq!(Numberf64(1.0).encode().len(), 2 + Numberf64::WIRE_LEN);
+    }
+
+    #[test]
+    fn encode_full_answer() {
+        let answer = || Answer::from((1, Some("E".to_string())));
+        assert_eq!(
+            answer().encode(AnswerOrder::MessageFirst).unwrap()[..],
+            [10u8, 13, 11, 1, b'E', 16, 8, 0, 0, 0, 0, 0, 0, 0, 1]
+        );
+        assert_eq!(
+            answer().encode(AnswerOrder::MessageLast).unwrap()[..],
+            [10u8, 13, 16, 8, 0, 0, 0, 0, 0, 0, 0, 1, 11, 1, b'E']
+        );
+    }
+
+    #[test]
+    fn parse_ordered_answer() {
+        for order in [AnswerOrder::MessageFirst, AnswerOrder::MessageLast] {
+            let encoded = Answer::from((1, Some("E".to_string())))
+                .encode(order)
+                .unwrap();
+            let tlv = || Tlv::try_from(&encoded[..]).unwrap();
+            assert!(Answer::try_from_ordered(tlv(), order).is_ok());
+
+            let wrong = match order {
+                AnswerOrder::MessageFirst => AnswerOrder::MessageLast,
+                AnswerOrder::MessageLast => AnswerOrder::MessageFirst,
+            };
+            assert!(matches!(
+                Answer::try_from_ordered(tlv(), wrong),
+                Err(TCPLibError::WrongAnswerOrder)
+            ));
+        }
+
+        // Without a message any order is fine
+        let encoded = Answer::from((1, None))
+            .encode(AnswerOrder::MessageFirst)
+            .unwrap();
+        let tlv = Tlv::try_from(&encoded[..]).unwrap();
+        assert!(Answer::try_from_ordered(tlv, AnswerOrder::MessageLast).is_ok());
+    }
+
+    #[test]
+    fn parse_answer_delta() {
+        let encoded = [
+            10u8, 20, 17, 8, 0, 0, 0, 0, 0, 0, 0, 7, 16, 8, 0, 0, 0, 0, 0, 0, 0, 42,
+        ];
+        let answer = Answer::try_from(Tlv::try_from(&encoded[..]).unwrap()).unwrap();
+        assert_eq!(answer.acc.0, 42);
+        assert_eq!(answer.delta, Some(Numberi64(7)));
+        assert_eq!(answer, Answer::ok(42).with_delta(7));
+        assert_eq!(
+            Answer::ok(42)
+                .with_delta(7)
+                .encode(AnswerOrder::MessageLast)
+                .unwrap()[..],
+            encoded
+        );
+
+        let encoded = Answer::ok(42).encode(AnswerOrder::MessageLast).unwrap();
+        let answer = Answer::try_from(Tlv::try_from(&encoded[..]).unwrap()).unwrap();
+        assert!(answer.delta.is_none());
+    }
+
+    #[test]
+    fn parse_strict_answer() {
+        let encoded = Answer::error(5, "boom")
+            .encode(AnswerOrder::MessageFirst)
+            .unwrap();
+        let answer = Answer::try_from_strict(Tlv::try_from(&encoded[..]).unwrap()).unwrap();
+        assert_eq!(answer, Answer::error(5, "boom"));
+
+        let encoded = Answer::try_from((vec![1, 2], None))
+            .unwrap()
+            .encode(AnswerOrder::MessageFirst)
+            .unwrap();
+        assert!(matches!(
+            Answer::try_from_strict(Tlv::try_from(&encoded[..]).unwrap()),
+            Err(TCPLibError::DuplicateTlv(TlvType::Numi64))
+        ));
+
+        let encoded = [
+            10u8, 16, 11, 1, b'a', 11, 1, b'b', 16, 8, 0, 0, 0, 0, 0, 0, 0, 1,
+        ];
+        assert!(matches!(
+            Answer::try_from_strict(Tlv::try_from(&encoded[..]).unwrap()),
+            Err(TCPLibError::DuplicateTlv(TlvType::Invalid))
+        ));
+    }
+
+    #[test]
+    fn parse_batch_answer() {
+        let frame = [
+            10u8, 33, 16, 8, 0, 0, 0, 0, 0, 0, 0, 1, 16, 8, 0, 0, 0, 0, 0, 0, 0, 2, 11, 1, b'E',
+            16, 8, 0, 0, 0, 0, 0, 0, 0, 3,
+        ];
+        let tlv: Tlv = (&frame[..]).try_into().unwrap();
+        let answer = Answer::try_from(tlv).unwrap();
+        assert_eq!(answer.partials, [1.into(), 2.into()]);
+        assert_eq!(answer.acc, 3.into());
+        assert_eq!(answer.message.unwrap().to_string(), "E");
+    }
+
+    #[test]
+    fn encode_batch_answer() {
+        let answer = || Answer::try_from((vec![1, 2, 3], Some("E".to_string()))).unwrap();
+        let encoded = answer().encode(AnswerOrder::MessageLast).unwrap();
+        assert_eq!(encoded[2..5], [16u8, 8, 0]);
+        assert_eq!(encoded[32..], [11u8, 1, b'E']);
+        let encoded = answer().encode(AnswerOrder::MessageFirst).unwrap();
+        assert_eq!(encoded[2..5], [11u8, 1, b'E']);
+
+        let tlv: Tlv = (&encoded[..]).try_into().unwrap();
+        let answer = Answer::try_from(tlv).unwrap();
+        assert_eq!(answer.values().map(|v| v.0).collect::<Vec<_>>(), [1, 2, 3]);
+
+        assert!(matches!(
+            Answer::try_from((vec![], None)),
+            Err(TCPLibError::EmptyAnswer)
+        ));
+        let most = Answer::try_from((vec![0; Answer::MAX_VALUES], None)).unwrap();
+        assert_eq!(
+            most.encode(AnswerOrder::MessageFirst).unwrap().len(),
+            2 + 250
+        );
+    }
+
+    #[test]
+    fn float_answer_round_trip() {
+        let encoded = Numberf64(7.0).encode();
+        assert_eq!(encoded[..], [18u8, 8, 0x40, 0x1c, 0, 0, 0, 0, 0, 0]);
+        let tlv: Tlv = (&encoded[..]).try_into().unwrap();
+        assert_eq!(Numberf64::try_from(tlv).unwrap(), 7.0.into());
+    }
 }
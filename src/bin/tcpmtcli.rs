@@ -21,12 +21,21 @@
  */
 
 use std::{
-    io::{stdin, Read, Write},
+    fmt::{self, Display},
+    fs::File,
+    io::{self, stdin, BufReader, BufWriter, Read, Write},
     net::{IpAddr, SocketAddr, TcpStream},
+    path::PathBuf,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
 };
 
-use clap::Parser;
-use tcpmt::{Answer, Operation, Tlv};
+use clap::{Parser, ValueEnum};
+use tcpmt::{
+    deframe, frame, hexdump, hexdump_annotated, read_tlv, read_version, write_frame, Answer,
+    FloatOperation, Logger, Operation, Tlv, TlvIterator, TlvType, Verbosity, PROTOCOL_VERSION,
+};
 
 #[derive(Debug, Parser)]
 struct Args {
@@ -35,38 +44,535 @@ struct Args {
     /// Destination port number
     #[arg(value_parser = clap::value_parser!(u16).range(1..))]
     dst_port: u16,
+
+    /// Print the operations sent. Repeat to also print the raw TLVs
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Do not print anything
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Do not connect to the server, just print the encoded operations
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+
+    /// Maximum time to wait for the connection to be established, in milliseconds
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    connect_timeout: Option<u64>,
+
+    /// Number of attempts to reconnect if the connection is lost
+    #[arg(long, default_value_t = 0)]
+    retries: u32,
+
+    /// Check that the connection is alive after this many seconds without input
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    keepalive: Option<u64>,
+
+    /// Print each result as a JSON line
+    #[arg(long)]
+    json: bool,
+
+    /// Prefix every message with its length in two bytes
+    #[arg(long)]
+    framed: bool,
+
+    /// Encode the operands with this many bits instead of the narrowest that fits
+    #[arg(long, value_enum)]
+    force_width: Option<Width>,
+
+    /// Enable Nagle's algorithm, delaying small operations to send them together
+    #[arg(long)]
+    nagle: bool,
+
+    /// Save the TLVs sent and received to this capture file
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Send the operations recorded in this capture file instead of reading them
+    #[arg(long, conflicts_with = "dry_run")]
+    replay: Option<PathBuf>,
+}
+
+/// A capture file starts with this, followed by one record per message. Each
+/// record has the direction of the message, [`SENT`] or [`RECEIVED`], its
+/// length in two bytes and the message itself.
+const CAPTURE_MAGIC: &[u8] = b"TCPMTCAP1\n";
+const SENT: u8 = b'>';
+const RECEIVED: u8 = b'<';
+
+/// Writes the messages exchanged with the server to a capture file
+struct Capture<W: Write>(W);
+
+impl<W: Write> Capture<W> {
+    fn new(mut w: W) -> io::Result<Self> {
+        w.write_all(CAPTURE_MAGIC)?;
+        Ok(Capture(w))
+    }
+
+    fn record(&mut self, direction: u8, message: &[u8]) -> io::Result<()> {
+        let len = u16::try_from(message.len())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        self.0.write_all(&[direction])?;
+        self.0.write_all(&len.to_be_bytes())?;
+        self.0.write_all(message)?;
+        self.0.flush()
+    }
+}
+
+/// Reads the records of a capture file as pairs of direction and message
+fn read_capture(mut r: impl Read) -> io::Result<Vec<(u8, Vec<u8>)>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not a capture file");
+    let mut magic = [0u8; CAPTURE_MAGIC.len()];
+    r.read_exact(&mut magic).map_err(|_| invalid())?;
+    if magic != CAPTURE_MAGIC {
+        return Err(invalid());
+    }
+
+    let mut records = vec![];
+    let mut header = [0u8; 3];
+    loop {
+        match r.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(records),
+            Err(e) => return Err(e),
+        }
+        if ![SENT, RECEIVED].contains(&header[0]) {
+            return Err(invalid());
+        }
+        let mut message = vec![0u8; u16::from_be_bytes([header[1], header[2]]) as usize];
+        r.read_exact(&mut message)?;
+        records.push((header[0], message));
+    }
+}
+
+/// Something to send to the server: a line written by the user or an
+/// operation from a capture file
+enum Input {
+    Line(String),
+    Replay(Request),
+}
+
+/// An operation with integer or decimal operands
+#[derive(Debug, PartialEq)]
+enum Request {
+    Int(Operation),
+    Float(FloatOperation),
+}
+
+impl Request {
+    /// Decodes an operation sent to the server
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        match Operation::try_from(bytes) {
+            Ok(operation) => Some(Request::Int(operation)),
+            Err(_) => Some(Request::Float(
+                Tlv::try_from_exact(bytes).ok()?.try_into().ok()?,
+            )),
+        }
+    }
+
+    fn encode(self) -> Box<[u8]> {
+        match self {
+            Request::Int(operation) => operation.encode(),
+            Request::Float(operation) => operation.encode(),
+        }
+    }
+}
+
+impl Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Request::Int(operation) => operation.fmt(f),
+            Request::Float(operation) => operation.fmt(f),
+        }
+    }
+}
+
+/// Width of the encoded operands
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Width {
+    #[value(name = "8")]
+    W8,
+    #[value(name = "16")]
+    W16,
+}
+
+/// Parses `line` with the requested operand width, or the narrowest one if
+/// none is given. Operands with a decimal point make a floating-point
+/// operation, whatever the width.
+fn parse_operation(line: &str, width: Option<Width>) -> anyhow::Result<Request> {
+    if line.contains('.') {
+        return Ok(Request::Float(line.parse()?));
+    }
+    let operation = match width {
+        None => line
+            .parse::<Operation>()
+            .or_else(|e| Operation::from_str_wide(line).map_err(|_| e))?,
+        Some(Width::W8) => line.parse()?,
+        Some(Width::W16) => Operation::from_str_wide(line)?,
+    };
+    Ok(Request::Int(operation))
+}
+
+/// Quotes `s` as a JSON string
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Formats the answer to `op` as
+/// `{"op":"3+4","acc":7,"result":null,"error":null}`, where `result` is the
+/// result of the operation on its own if the server sent it
+fn json_line(op: &str, answer: &Answer) -> String {
+    format!(
+        "{{\"op\":{},\"acc\":{},\"result\":{},\"error\":{}}}",
+        json_string(op),
+        answer.acc,
+        answer
+            .result
+            .as_ref()
+            .map_or("null".into(), |result| result.to_string()),
+        answer
+            .message
+            .as_ref()
+            .map_or("null".into(), |m| json_string(&m.to_string()))
+    )
+}
+
+/// A connection to the server with buffered reads and writes
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+    /// Protocol version announced by the server, if any
+    version: Option<u8>,
+    /// Whether messages are prefixed with their length
+    framed: bool,
+}
+
+impl Connection {
+    fn open(
+        address: SocketAddr,
+        timeout: Option<Duration>,
+        framed: bool,
+        nagle: bool,
+    ) -> io::Result<Self> {
+        let stream = match timeout {
+            Some(timeout) => TcpStream::connect_timeout(&address, timeout),
+            None => TcpStream::connect(address),
+        }?;
+        stream.set_nodelay(!nagle)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let version = read_version(&mut reader, framed)?;
+        Ok(Connection {
+            reader,
+            writer: BufWriter::new(stream),
+            version,
+            framed,
+        })
+    }
+
+    /// Reads a whole TLV, or a whole message if they are prefixed with their
+    /// length
+    fn read_tlv(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.framed {
+            let message = deframe(&mut self.reader)?;
+            let len = message.len().min(buffer.len());
+            buffer[..len].copy_from_slice(&message[..len]);
+            return Ok(len);
+        }
+        read_tlv(&mut self.reader, buffer)
+    }
+
+    /// Sends a request and reads the whole reply TLV, along with any other
+    /// complete TLVs that arrived with it
+    fn exchange(&mut self, request: &[u8], buffer: &mut [u8]) -> io::Result<usize> {
+        match self.framed {
+            true => write_frame(&mut self.writer, &frame(request))?,
+            false => write_frame(&mut self.writer, request)?,
+        }
+        let mut len = self.read_tlv(buffer)?;
+        while !self.framed
+            && buffer.len() - len >= 2 + u8::MAX as usize
+            && Tlv::try_from(self.reader.buffer()).is_ok()
+        {
+            len += self.read_tlv(&mut buffer[len..])?;
+        }
+        Ok(len)
+    }
+
+    /// Warns if the server speaks a newer protocol than this client, or
+    /// did not say which one it speaks
+    fn check_version(&self, log: &Logger) {
+        match self.version {
+            Some(version) if version > PROTOCOL_VERSION => log.error(format_args!(
+                "Warning: the server uses protocol version {version}, newer than the supported {PROTOCOL_VERSION}"
+            )),
+            Some(_) => {}
+            None => log.error(format_args!(
+                "Warning: the server did not announce its protocol version"
+            )),
+        }
+    }
 }
 
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
+    let log = Logger::new(Verbosity::from_flags(args.verbose, args.quiet));
+
+    let address = SocketAddr::from((args.ip, args.dst_port));
+    let timeout = args.connect_timeout.map(Duration::from_millis);
 
     let mut buffer = [0u8; 2048];
-    let mut stream = TcpStream::connect(SocketAddr::from((args.ip, args.dst_port)))?;
+    let mut stream = match args.dry_run {
+        true => None,
+        false => Some(Connection::open(address, timeout, args.framed, args.nagle)?),
+    };
+    if let Some(stream) = &stream {
+        stream.check_version(&log);
+    }
+
+    if !args.json {
+        log.print(format_args!(
+            "Enter arithmetic expressions using infix notation. For example: 10 * 3 or 5!."
+        ));
+    }
 
-    println!("Enter arithmetic expressions using infix notation. For example: 10 * 3 or 5!.");
+    let mut capture = match &args.record {
+        Some(path) => Some(Capture::new(BufWriter::new(File::create(path)?))?),
+        None => None,
+    };
+    let replay: Option<Vec<Request>> = match &args.replay {
+        Some(path) => Some(
+            read_capture(BufReader::new(File::open(path)?))?
+                .into_iter()
+                .filter(|(direction, _)| *direction == SENT)
+                .filter_map(|(_, message)| Request::decode(&message))
+                .collect(),
+        ),
+        None => None,
+    };
 
-    for line in stdin().lines() {
-        let iline = line?;
-        if iline.trim() == "QUIT" {
-            break;
+    // Read the input in the background so that we can send keepalives meanwhile
+    let (tx, lines) = mpsc::channel();
+    thread::spawn(move || match replay {
+        Some(operations) => {
+            for operation in operations {
+                if tx.send(Ok(Input::Replay(operation))).is_err() {
+                    break;
+                }
+            }
+        }
+        None => {
+            for line in stdin().lines() {
+                if tx.send(line.map(Input::Line)).is_err() {
+                    break;
+                }
+            }
         }
-        match iline.parse::<Operation>() {
+    });
+    let keepalive = args.keepalive.map(Duration::from_secs);
+
+    loop {
+        let line = match keepalive {
+            Some(keepalive) => lines.recv_timeout(keepalive),
+            None => lines.recv().map_err(RecvTimeoutError::from),
+        };
+        let input = match line {
+            Ok(input) => input?,
+            Err(RecvTimeoutError::Timeout) => {
+                if let Some(stream) = stream.as_mut() {
+                    let ping = Tlv::new(TlvType::Ping, &[])?.encode();
+                    let len = stream.exchange(&ping, &mut buffer)?;
+                    if let Some(capture) = capture.as_mut() {
+                        capture.record(SENT, &ping)?;
+                        capture.record(RECEIVED, &buffer[..len])?;
+                    }
+                    match Tlv::try_from_exact(&buffer[..len])?.tag {
+                        TlvType::Pong => log.debug(format_args!("Keepalive answered")),
+                        tag => log.error(format_args!("Unexpected answer to keepalive: {tag:?}")),
+                    }
+                }
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        let operation = match input {
+            Input::Line(line) if line.trim() == "QUIT" => break,
+            Input::Line(line) => parse_operation(&line, args.force_width),
+            Input::Replay(operation) => Ok(operation),
+        };
+        match operation {
+            Ok(operation) if stream.is_none() => {
+                log.verbose(format_args!("Encoding {operation}"));
+                log.print(format_args!("{}", hexdump(&operation.encode())));
+            }
             Ok(operation) => {
-                stream.write_all(&operation.encode())?;
-                let len = stream.read(&mut buffer)?;
-                let answer: Answer = Tlv::try_from(&buffer[..len])?.try_into()?;
-                println!(
-                    "Accumulator: {}{}",
-                    answer.acc,
-                    match answer.message {
-                        Some(m) => format!(" Error: {}", m),
-                        _ => "".into(),
+                let stream = stream.as_mut().unwrap();
+                log.verbose(format_args!("Sending {operation}"));
+                let op = operation.to_string();
+                let encoded = operation.encode();
+                log.debug(format_args!("Sent {}", hexdump_annotated(&encoded)));
+                let len = match stream.exchange(&encoded, &mut buffer) {
+                    Ok(len) => len,
+                    Err(e) if args.retries > 0 => {
+                        log.error(format_args!(
+                            "Connection lost ({e}). The last operation was not answered."
+                        ));
+                        *stream = (1..=args.retries)
+                            .find_map(|attempt| {
+                                log.error(format_args!(
+                                    "Reconnecting ({attempt}/{})...",
+                                    args.retries
+                                ));
+                                Connection::open(address, timeout, args.framed, args.nagle).ok()
+                            })
+                            .ok_or_else(|| anyhow::anyhow!("Could not reconnect to {address}"))?;
+                        log.error(format_args!("Reconnected to {address}"));
+                        stream.check_version(&log);
+                        continue;
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                if let Some(capture) = capture.as_mut() {
+                    capture.record(SENT, &encoded)?;
+                    capture.record(RECEIVED, &buffer[..len])?;
+                }
+                log.debug(format_args!(
+                    "Received {}",
+                    hexdump_annotated(&buffer[..len])
+                ));
+                let tlvs = TlvIterator::process(&buffer[..len]).filter(|tlv| !tlv.tag.is_control());
+                for tlv in tlvs {
+                    let answer = Answer::try_from(tlv)?;
+                    if args.json {
+                        log.print(format_args!("{}", json_line(&op, &answer)));
+                    } else if let Some(result) = answer.float {
+                        log.print(format_args!("Result: {result}"));
+                    } else if let Some(result) = answer.result {
+                        log.print(format_args!("Result: {result} Accumulator: {}", answer.acc));
+                    } else if let Some(delta) = answer.delta {
+                        log.print(format_args!("{:+} => {}", delta.0, answer.acc));
+                    } else {
+                        log.print(format_args!(
+                            "Accumulator: {}{}",
+                            answer.acc,
+                            match answer.message {
+                                Some(m) => format!(" Error: {}", m),
+                                _ => "".into(),
+                            }
+                        ));
                     }
-                );
+                }
             }
-            Err(_) => println!("Could not parse operation. Please, try again."),
+            Err(e) => log.error(format_args!("Invalid input: {e}. Please, try again.")),
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        net::{Ipv4Addr, TcpListener},
+        thread,
+    };
+
+    use tcpmt::{Answer, FloatOperation, Operation, PROTOCOL_VERSION};
+
+    use crate::{
+        json_line, parse_operation, read_capture, Capture, Connection, Request, Width, RECEIVED,
+        SENT,
+    };
+
+    #[test]
+    fn nagle() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                stream.write_all(&[40, 1, PROTOCOL_VERSION]).unwrap();
+            }
+        });
+
+        let connection = Connection::open(address, None, false, false).unwrap();
+        assert!(connection.writer.get_ref().nodelay().unwrap());
+        let connection = Connection::open(address, None, false, true).unwrap();
+        assert!(!connection.writer.get_ref().nodelay().unwrap());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn capture_round_trip() {
+        let mut capture = Capture::new(vec![]).unwrap();
+        capture.record(SENT, &[1, 2, 3, 4]).unwrap();
+        capture.record(RECEIVED, &[]).unwrap();
+        let records = read_capture(&capture.0[..]).unwrap();
+        assert_eq!(records, [(SENT, vec![1, 2, 3, 4]), (RECEIVED, vec![])]);
+
+        assert!(read_capture(&b"garbage"[..]).is_err());
+        assert!(read_capture(&capture.0[..capture.0.len() - 4]).is_err());
+    }
+
+    #[test]
+    fn json_output() {
+        assert_eq!(
+            json_line("3+4", &Answer::from((7, None))),
+            r#"{"op":"3+4","acc":7,"result":null,"error":null}"#
+        );
+        assert_eq!(
+            json_line("3÷0", &Answer::from((0, Some("Wrong \"domain\"".into())))),
+            r#"{"op":"3÷0","acc":0,"result":null,"error":"Wrong \"domain\""}"#
+        );
+        assert_eq!(
+            json_line("3×4", &Answer::ok(112).with_result(12)),
+            r#"{"op":"3×4","acc":112,"result":12,"error":null}"#
+        );
+    }
+
+    #[test]
+    fn operand_width() {
+        assert_eq!(
+            parse_operation("3+4", None).unwrap(),
+            Request::Int(Operation::Sum((3, 4).into()))
+        );
+        assert_eq!(
+            parse_operation("1000+4", None).unwrap(),
+            Request::Int(Operation::WSum((1000, 4).into()))
+        );
+        assert_eq!(
+            parse_operation("3+4", Some(Width::W16)).unwrap(),
+            Request::Int(Operation::WSum((3, 4).into()))
+        );
+        assert_eq!(
+            parse_operation("3+4", Some(Width::W8)).unwrap(),
+            Request::Int(Operation::Sum((3, 4).into()))
+        );
+        assert_eq!(
+            parse_operation("1000+4", Some(Width::W8))
+                .unwrap_err()
+                .to_string(),
+            "1000 doesn't fit in a single byte"
+        );
+        assert!(parse_operation("5!", Some(Width::W16)).is_err());
+        assert!(parse_operation("40000+1", None).is_err());
+        assert_eq!(
+            parse_operation("3.5 * 2", Some(Width::W8)).unwrap(),
+            Request::Float(FloatOperation::Mul((3.5, 2.0).into()))
+        );
+        assert!(parse_operation("3.5!", None).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Load generator: sends random operations to a server and measures how
+//! long it takes to answer them.
+
+use std::{
+    io::{BufReader, BufWriter},
+    net::{IpAddr, SocketAddr, TcpStream},
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use tcpmt::{read_tlv, read_version, write_frame, Answer, Operation, Tlv, XorShift};
+
+#[derive(Debug, Parser)]
+struct Args {
+    /// Destination IP Address
+    ip: IpAddr,
+    /// Destination port number
+    #[arg(value_parser = clap::value_parser!(u16).range(1..))]
+    dst_port: u16,
+
+    /// Number of operations to send
+    #[arg(short, long, default_value_t = 1000)]
+    count: u32,
+
+    /// Seed of the random generator, so that runs can be repeated
+    #[arg(long, default_value_t = 0x5eed, value_parser = clap::value_parser!(u64).range(1..))]
+    seed: u64,
+}
+
+/// A random operation that the server can compute
+fn random_operation(rng: &mut XorShift) -> Operation {
+    let (a, b) = (rng.next_i8(), rng.next_i8());
+    // Avoid dividing by zero and the overflow of -128 / -1
+    let divisor = b.max(1);
+    match rng.next_u64() % 6 {
+        0 => Operation::Sum((a, b).into()),
+        1 => Operation::Sub((a, b).into()),
+        2 => Operation::Mul((a, b).into()),
+        3 => Operation::Div((a, divisor).into()),
+        4 => Operation::Rem((a, divisor).into()),
+        _ => Operation::Fact(((a as u8 % 21) as i8).into()),
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let address = SocketAddr::from((args.ip, args.dst_port));
+
+    let stream = TcpStream::connect(address)?;
+    stream.set_nodelay(true)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    let mut buffer = [0u8; 257];
+
+    if read_version(&mut reader, false)?.is_none() {
+        eprintln!("Warning: the server did not announce its protocol version");
+    }
+
+    let mut rng = XorShift::new(args.seed);
+    let mut latencies = Vec::with_capacity(args.count as usize);
+    let mut errors = 0;
+    let start = Instant::now();
+    for _ in 0..args.count {
+        let operation = random_operation(&mut rng);
+        let sent = Instant::now();
+        write_frame(&mut writer, &operation.encode())?;
+        let len = read_tlv(&mut reader, &mut buffer)?;
+        latencies.push(sent.elapsed());
+
+        let answer = Answer::try_from(Tlv::try_from(&buffer[..len])?)?;
+        if answer.message.is_some() {
+            errors += 1;
+        }
+    }
+    let elapsed = start.elapsed();
+
+    let total: Duration = latencies.iter().sum();
+    println!(
+        "{} operations ({errors} failed) in {elapsed:.2?}: {:.0} operations/s",
+        args.count,
+        f64::from(args.count) / elapsed.as_secs_f64()
+    );
+    if let (Some(min), Some(max)) = (latencies.iter().min(), latencies.iter().max()) {
+        println!(
+            "Round-trip time: min {min:.2?}, avg {:.2?}, max {max:.2?}",
+            total / args.count
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tcpmt::{Operation, XorShift};
+
+    use crate::random_operation;
+
+    fn generate(seed: u64) -> Vec<Operation> {
+        let mut rng = XorShift::new(seed);
+        (0..100).map(|_| random_operation(&mut rng)).collect()
+    }
+
+    #[test]
+    fn deterministic_operations() {
+        assert_eq!(generate(0x5eed), generate(0x5eed));
+        assert_ne!(generate(0x5eed), generate(0xf00d));
+        for operation in generate(0x5eed) {
+            assert!(operation.reduce().is_ok(), "{operation} cannot be computed");
+        }
+    }
+}
@@ -21,79 +21,719 @@
  */
 
 use std::{
-    io::{Read, Write},
-    net::{Ipv6Addr, SocketAddr, TcpListener},
-    thread,
+    collections::HashMap,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
 };
 
+use anyhow::bail;
 use clap::Parser;
 use socket2::{Domain, Socket, Type};
-use tcpmt::{Answer, AnswerOrder, Operation, TlvIterator};
+use tcpmt::{
+    compute_operation, count_operation, deframe, hexdump_annotated, reply_frame, stats_summary,
+    write_frame, Answer, AnswerOrder, Lang, Logger, ReplyOptions, Stats, Tlv, TlvIterator, TlvType,
+    Verbosity, XorShift, PROTOCOL_VERSION,
+};
 
 #[derive(Debug, Parser)]
 struct Args {
     /// Port number
-    #[arg(value_parser = clap::value_parser!(u16).range(1..))]
+    #[arg(env = "TCPMT_PORT", value_parser = clap::value_parser!(u16).range(1..))]
     port: u16,
 
     /// Answer order
     #[arg(short, long)]
     message_last: bool,
+
+    /// Answer all the operations received together in a single answer
+    #[arg(short, long)]
+    batch: bool,
+
+    /// Accept only IPv4 connections
+    #[arg(short = '4', long)]
+    ipv4_only: bool,
+
+    /// Listen only on the interface with this address
+    #[arg(long, alias = "listen-address")]
+    bind: Option<IpAddr>,
+
+    /// Maximum number of simultaneous connections
+    #[arg(long)]
+    max_connections: Option<NonZeroUsize>,
+
+    /// Reject the messages not ending with a valid checksum. Needs --framed,
+    /// so that the checksum covers whole messages and not whatever TLVs
+    /// arrived together
+    #[arg(long, requires = "framed")]
+    require_checksum: bool,
+
+    /// Maximum number of pending connections
+    #[arg(long, default_value_t = 128, value_parser = clap::value_parser!(i32).range(1..))]
+    backlog: i32,
+
+    /// Answer with the text of the operations received instead of computing them
+    #[arg(long)]
+    echo: bool,
+
+    /// Include in each answer the change of the accumulator
+    #[arg(long, conflicts_with = "batch")]
+    delta: bool,
+
+    /// Include in each answer the result of the operation on its own
+    #[arg(long, conflicts_with = "batch")]
+    op_result: bool,
+
+    /// Keep the accumulator of each client address across connections
+    #[arg(long)]
+    sticky: bool,
+
+    /// Value of the accumulator of new clients
+    #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+    initial_acc: i64,
+
+    /// Size in bytes of the buffer used to read from each connection
+    #[arg(long, default_value_t = 2048, value_parser = clap::value_parser!(u32).range(64..))]
+    buffer_size: u32,
+
+    /// Language of the error messages: en, gl or es
+    #[arg(long, default_value_t = Lang::En)]
+    lang: Lang,
+
+    /// Prefix every message with its length in two bytes
+    #[arg(long)]
+    framed: bool,
+
+    /// Milliseconds to wait before sending each reply
+    #[arg(long, default_value_t = 0)]
+    delay: u64,
+
+    /// Largest number of milliseconds randomly added to the delay
+    #[arg(long, default_value_t = 0)]
+    jitter: u64,
+
+    /// Seed of the random generator of the jitter, so that runs can be repeated
+    #[arg(long, default_value_t = 0x5eed, value_parser = clap::value_parser!(u64).range(1..))]
+    seed: u64,
+
+    /// Enable Nagle's algorithm, delaying small answers to send them together
+    #[arg(long)]
+    nagle: bool,
+
+    /// Also accept operations written as text, one per line, on this port
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    text_port: Option<u16>,
+
+    /// Print each operation. Repeat to also print the raw TLVs
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Do not print anything
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+}
+
+/// Largest number of TLVs processed together
+const MAX_FRAME_TLVS: usize = 256;
+
+/// Largest number of TLVs processed from a length-prefixed message, so that
+/// the answers, of up to 257 bytes each, fit in the length-prefixed reply
+const MAX_FRAMED_TLVS: usize = u16::MAX as usize / 257;
+
+/// Keeps track of an active connection, releasing it when dropped
+struct ConnectionGuard(Arc<AtomicUsize>);
+
+impl ConnectionGuard {
+    fn new(active: &Arc<AtomicUsize>) -> Self {
+        active.fetch_add(1, Ordering::SeqCst);
+        Self(active.clone())
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Time to wait before a reply: `delay` plus up to `jitter` milliseconds
+fn reply_delay(rng: &mut XorShift, delay: u64, jitter: u64) -> Duration {
+    let jitter = match jitter {
+        0 => 0,
+        jitter => rng.next_u64() % (jitter + 1),
+    };
+    Duration::from_millis(delay.saturating_add(jitter))
+}
+
+/// Enables or disables Nagle's algorithm in `stream`
+fn set_nagle(stream: &TcpStream, nagle: bool) -> io::Result<()> {
+    stream.set_nodelay(!nagle)
+}
+
+/// Waits for more data from the client. Returns `false` if the client has
+/// closed the connection, telling it apart from a failed read.
+fn wait_data<R: BufRead>(reader: &mut R) -> io::Result<bool> {
+    Ok(!reader.fill_buf()?.is_empty())
+}
+
+/// Joins the connection threads that have already finished, keeping the rest
+fn reap(workers: &mut Vec<JoinHandle<()>>) {
+    let (finished, running): (Vec<_>, Vec<_>) =
+        workers.drain(..).partition(|worker| worker.is_finished());
+    *workers = running;
+    for worker in finished {
+        let _ = worker.join(); // A panic only affects its own connection
+    }
+}
+
+/// Shows IPv4-mapped IPv6 addresses as plain IPv4 ones
+fn unmap(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(ip) => SocketAddr::from((ip, v6.port())),
+            None => addr,
+        },
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+/// Text answer to an operation: the accumulator and the error, if any
+fn text_answer(answer: &Answer) -> String {
+    match &answer.message {
+        Some(message) => format!("{} Error: {message}\n", answer.acc),
+        None => format!("{}\n", answer.acc),
+    }
+}
+
+/// Settings of the server shared by all the connections
+#[derive(Clone, Copy, Debug)]
+struct Config {
+    options: ReplyOptions,
+    initial_acc: i64,
+    /// Size of the buffer used to read from each connection
+    buffer_size: usize,
+    framed: bool,
+    delay: u64,
+    jitter: u64,
+    seed: u64,
+    log: Logger,
+}
+
+/// State of the server shared by all the connections
+#[derive(Debug, Default)]
+struct Shared {
+    /// Accumulator of each client address, kept across connections
+    sticky: Option<Mutex<HashMap<IpAddr, i64>>>,
+    stats: Mutex<Stats>,
+}
+
+/// Prints the summary of the operations computed and exits once the server
+/// is interrupted or terminated. It must be called before starting any other
+/// thread, so that they all inherit the blocked signals and only the thread
+/// started here receives them.
+#[cfg(unix)]
+fn summary_on_shutdown(shared: Arc<Shared>, log: Logger) {
+    // SAFETY: the signal set is initialized by sigemptyset before being used
+    let signals = unsafe {
+        let mut signals = std::mem::zeroed::<libc::sigset_t>();
+        libc::sigemptyset(&mut signals);
+        libc::sigaddset(&mut signals, libc::SIGINT);
+        libc::sigaddset(&mut signals, libc::SIGTERM);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &signals, std::ptr::null_mut());
+        signals
+    };
+    thread::spawn(move || {
+        let mut signal = 0;
+        // SAFETY: both pointers are valid for the duration of the call
+        if unsafe { libc::sigwait(&signals, &mut signal) } == 0 {
+            log.print(format_args!(
+                "{}",
+                stats_summary(&shared.stats.lock().unwrap())
+            ));
+            std::process::exit(0);
+        }
+    });
+}
+
+/// Computes the operations written as text by the client, one per line,
+/// starting with the initial accumulator, and counts them in the stats
+fn serve_text(stream: TcpStream, addr: SocketAddr, config: &Config, shared: &Shared) {
+    let reader = match stream.try_clone() {
+        Ok(stream) => BufReader::new(stream),
+        Err(_) => return,
+    };
+    let mut writer = BufWriter::new(stream);
+    let mut acc = config.initial_acc;
+    for line in reader.lines() {
+        let Ok(line) = line else {
+            return; // Probably the client has closed the connection
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let answer = compute_operation(
+            line.parse(),
+            &mut acc,
+            config.options.lang,
+            |operation, result| {
+                config
+                    .log
+                    .verbose(format_args!("{addr}: {operation} = {result}"));
+                count_operation(&shared.stats, operation);
+            },
+        );
+        if write_frame(&mut writer, text_answer(&answer).as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+/// Sends the protocol version to the client connected from `addr` and then
+/// answers the operations it sends until it closes the connection. Fails if
+/// the operations cannot be read or answered.
+fn serve_connection(
+    stream: TcpStream,
+    addr: SocketAddr,
+    config: &Config,
+    shared: &Shared,
+) -> io::Result<()> {
+    let mut reader = BufReader::with_capacity(config.buffer_size, stream.try_clone()?);
+    serve(
+        &mut reader,
+        &mut BufWriter::new(stream),
+        addr,
+        config,
+        shared,
+    )
+}
+
+/// Like [`serve_connection`], reading and writing through any buffered
+/// stream. Each reply is written and flushed at once, however many answers
+/// it holds.
+fn serve<R: Read, W: Write>(
+    reader: &mut BufReader<R>,
+    writer: &mut BufWriter<W>,
+    addr: SocketAddr,
+    config: &Config,
+    shared: &Shared,
+) -> io::Result<()> {
+    let log = config.log;
+    let mut acc = shared.sticky.as_ref().map_or(config.initial_acc, |accs| {
+        *accs
+            .lock()
+            .unwrap()
+            .get(&addr.ip())
+            .unwrap_or(&config.initial_acc)
+    });
+    let version = Tlv::new(TlvType::Version, &[PROTOCOL_VERSION])
+        .unwrap()
+        .encode();
+    let version = match config.framed {
+        true => tcpmt::frame(&version),
+        false => version.into_vec(),
+    };
+    if write_frame(writer, &version).is_err() {
+        return Ok(()); // The client is already gone
+    }
+
+    let mut frame = Vec::with_capacity(config.buffer_size);
+    let mut rng = XorShift::new(config.seed);
+    let mut tlvs = 0;
+    loop {
+        if !wait_data(reader)? {
+            log.verbose(format_args!("{addr} closed connection"));
+            return Ok(());
+        }
+        if config.framed {
+            frame = deframe(reader)?;
+            let mut tlvs = TlvIterator::with_limit(&frame, MAX_FRAMED_TLVS);
+            tlvs.by_ref().for_each(drop);
+            if tlvs.peek().is_some() {
+                log.error(format_args!(
+                    "{addr}: Discarding the TLVs after the first {MAX_FRAMED_TLVS}"
+                ));
+                let end = tlvs.position();
+                frame.truncate(end);
+            }
+        } else {
+            // Collect whole TLVs until there is nothing else already received,
+            // but do not let a busy client grow the frame without bounds
+            let mut header = [0u8; 2];
+            let start = frame.len() + header.len();
+            reader.read_exact(&mut header)?;
+            frame.extend_from_slice(&header);
+            frame.resize(start + header[1] as usize, 0);
+            reader.read_exact(&mut frame[start..])?;
+            tlvs += 1;
+            if !reader.buffer().is_empty() && tlvs < MAX_FRAME_TLVS {
+                continue;
+            }
+        }
+
+        log.debug(format_args!(
+            "{addr}: received {}",
+            hexdump_annotated(&frame)
+        ));
+        let reply = reply_frame(
+            &frame,
+            &mut acc,
+            &config.options,
+            &shared.stats,
+            |operation, result| log.verbose(format_args!("{addr}: {operation} = {result}")),
+        )
+        .map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Could not encode the answers. {e}"),
+            )
+        })?;
+        if let Some(accs) = &shared.sticky {
+            accs.lock().unwrap().insert(addr.ip(), acc);
+        }
+        for error in &reply.errors {
+            log.error(format_args!("{addr}: {error}"));
+        }
+        if let Some(stats) = &reply.stats {
+            log.print(format_args!("{addr}: {stats}"));
+        }
+        let reply = match config.framed {
+            true => tcpmt::frame(&reply.bytes),
+            false => reply.bytes,
+        };
+        frame.clear();
+        tlvs = 0;
+
+        let wait = reply_delay(&mut rng, config.delay, config.jitter);
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+        if write_frame(writer, &reply).is_err() {
+            // Problably the connection to the client has been lost
+            return Ok(());
+        }
+    }
 }
 
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
+    let log = Logger::new(Verbosity::from_flags(args.verbose, args.quiet));
+
+    let address = match (args.bind, args.ipv4_only) {
+        (Some(IpAddr::V6(address)), true) => {
+            bail!("Cannot listen on IPv6 address {address} with --ipv4-only")
+        }
+        (Some(address), _) => address,
+        (None, true) => Ipv4Addr::UNSPECIFIED.into(),
+        (None, false) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let address = SocketAddr::from((address, args.port));
+    let domain = Domain::for_address(address);
 
     // We need to use the socket2 create to properly support Windows
-    let socket = Socket::new(Domain::IPV6, Type::STREAM, None)?;
-    socket.set_only_v6(false)?;
+    let socket = Socket::new(domain, Type::STREAM, None)?;
+    if domain == Domain::IPV6 {
+        socket.set_only_v6(false)?;
+    }
     socket.set_reuse_address(true)?;
-    socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, args.port)).into())?;
-    socket.listen(128)?;
+    socket.bind(&address.into())?;
+    socket.listen(args.backlog)?;
     let listener: TcpListener = socket.into();
+    log.verbose(format_args!(
+        "Listening on {address} with a backlog of {}",
+        args.backlog
+    ));
 
-    let order = match args.message_last {
-        true => AnswerOrder::MessageLast,
-        false => AnswerOrder::MessageFirst,
+    let nagle = args.nagle;
+    let config = Config {
+        options: ReplyOptions {
+            order: match args.message_last {
+                true => AnswerOrder::MessageLast,
+                false => AnswerOrder::MessageFirst,
+            },
+            lang: args.lang,
+            batch: args.batch,
+            require_checksum: args.require_checksum,
+            echo: args.echo,
+            delta: args.delta,
+            op_result: args.op_result,
+        },
+        initial_acc: args.initial_acc,
+        buffer_size: args.buffer_size as usize,
+        framed: args.framed,
+        delay: args.delay,
+        jitter: args.jitter,
+        seed: args.seed,
+        log,
     };
+    let shared = Arc::new(Shared {
+        sticky: args.sticky.then(Default::default),
+        ..Default::default()
+    });
+    #[cfg(unix)]
+    summary_on_shutdown(shared.clone(), log);
+    if let Some(port) = args.text_port {
+        let shared = shared.clone();
+        let text_listener = TcpListener::bind((address.ip(), port))?;
+        log.verbose(format_args!("Listening for text operations on port {port}"));
+        thread::spawn(move || loop {
+            if let Ok((stream, addr)) = text_listener.accept() {
+                let addr = unmap(addr);
+                if let Err(e) = set_nagle(&stream, nagle) {
+                    log.error(format_args!("{addr}: {e}"));
+                }
+                let shared = shared.clone();
+                thread::spawn(move || serve_text(stream, addr, &config, &shared));
+            }
+        });
+    }
 
+    let active = Arc::new(AtomicUsize::new(0));
+    let mut workers = Vec::new();
     loop {
-        let (mut stream, addr) = listener.accept()?;
-        thread::spawn(move || {
-            let mut acc = 0i64;
-            let mut buffer = [0u8; 2048];
-            loop {
-                match stream.read(&mut buffer) {
-                    Ok(len) if len > 0 => {
-                        for tlv in TlvIterator::process(&buffer[..len]) {
-                            let res = tlv
-                                .try_into()
-                                .and_then(|op: Operation| (op.reduce().map(|res| (op, res))));
-                            let answer = match res {
-                                Ok((operation, result)) => {
-                                    acc = acc.saturating_add(result);
-
-                                    println!("{addr}: {operation} = {result}");
-                                    (acc, None)
-                                }
-                                Err(ref e) => {
-                                    eprintln!("{addr}: Could not calculate answer. {}", e.clone());
-                                    (acc, Some(e.to_string()))
-                                }
-                            };
-
-                            if stream
-                                .write_all(&Answer::from(answer).encode(order))
-                                .is_err()
-                            {
-                                // Problably the connection to the client has been lost
-                                return;
-                            }
-                        }
-                    }
-                    _ => return, // Probably the client has closed the connection
+        let (stream, addr) = match listener.accept() {
+            Ok(connection) => connection,
+            Err(e) => {
+                log.print(format_args!(
+                    "{}",
+                    stats_summary(&shared.stats.lock().unwrap())
+                ));
+                return Err(e.into());
+            }
+        };
+        let addr = unmap(addr);
+        reap(&mut workers);
+        if let Err(e) = set_nagle(&stream, nagle) {
+            log.error(format_args!("{addr}: {e}"));
+        }
+        if let Some(max) = args.max_connections {
+            if active.load(Ordering::SeqCst) >= max.get() {
+                log.error(format_args!("refusing {addr}: connection limit reached"));
+                continue; // Dropping the stream closes the connection
+            }
+        }
+
+        let guard = ConnectionGuard::new(&active);
+        let shared = shared.clone();
+        let worker = thread::Builder::new()
+            .name(addr.to_string())
+            .spawn(move || {
+                let _guard = guard;
+                if let Err(e) = serve_connection(stream, addr, &config, &shared) {
+                    log.error(format_args!("{addr}: {e}"));
                 }
+            });
+        match worker {
+            Ok(worker) => workers.push(worker),
+            Err(e) => log.error(format_args!("refusing {addr}: {e}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, BufReader, BufWriter},
+        net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+        sync::mpsc,
+        thread,
+        time::Duration,
+    };
+
+    use clap::Parser;
+
+    use tcpmt::{
+        Answer, Logger, Operation, ReplyOptions, TlvIterator, TlvType, Verbosity, XorShift,
+    };
+
+    use crate::{
+        reap, reply_delay, serve, set_nagle, unmap, wait_data, Args, Config, Shared, MAX_FRAME_TLVS,
+    };
+
+    #[test]
+    fn nagle() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        set_nagle(&stream, false).unwrap();
+        assert!(stream.nodelay().unwrap());
+        set_nagle(&stream, true).unwrap();
+        assert!(!stream.nodelay().unwrap());
+
+        assert!(!Args::try_parse_from(["tcpmtser", "2000"]).unwrap().nagle);
+        assert!(
+            Args::try_parse_from(["tcpmtser", "2000", "--nagle"])
+                .unwrap()
+                .nagle
+        );
+    }
+
+    /// A writer that counts how many writes reach it
+    #[derive(Default)]
+    struct Counting {
+        writes: usize,
+        written: Vec<u8>,
+    }
+
+    impl io::Write for Counting {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes += 1;
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn buffered_writes() {
+        let config = Config {
+            options: ReplyOptions::default(),
+            initial_acc: 0,
+            buffer_size: 2048,
+            framed: false,
+            delay: 0,
+            jitter: 0,
+            seed: 1,
+            log: Logger::new(Verbosity::Quiet),
+        };
+        let request: Vec<u8> = (0..1000)
+            .flat_map(|_| Operation::Sum((1, 1).into()).encode().into_vec())
+            .collect();
+        let mut reader = BufReader::with_capacity(config.buffer_size, &request[..]);
+        let mut writer = BufWriter::new(Counting::default());
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 2000));
+        serve(&mut reader, &mut writer, addr, &config, &Shared::default()).unwrap();
+
+        // Writing each answer on its own would take 1000 writes. Instead,
+        // there is one for the version and one per frame of operations.
+        let Counting { writes, written } = writer.into_inner().ok().unwrap();
+        assert_eq!(writes, 1 + 1000usize.div_ceil(MAX_FRAME_TLVS));
+
+        let mut tlvs = TlvIterator::process(&written);
+        assert_eq!(tlvs.next().unwrap().tag, TlvType::Version);
+        let accs: Vec<_> = tlvs
+            .map(|tlv| Answer::try_from(tlv).unwrap().acc.0)
+            .collect();
+        assert_eq!(accs, (1..=1000).map(|i| 2 * i).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn delay_with_jitter() {
+        let mut rng = XorShift::new(1);
+        assert_eq!(reply_delay(&mut rng, 0, 0), Duration::ZERO);
+        assert_eq!(reply_delay(&mut rng, 30, 0), Duration::from_millis(30));
+
+        let delays: Vec<_> = (0..100).map(|_| reply_delay(&mut rng, 30, 20)).collect();
+        assert!(delays
+            .iter()
+            .all(|delay| (30..=50).contains(&delay.as_millis())));
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+
+        // The same seed gives the same delays
+        let run = |seed| {
+            let mut rng = XorShift::new(seed);
+            (0..10)
+                .map(|_| reply_delay(&mut rng, 30, 20))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(run(9), run(9));
+    }
+
+    #[test]
+    fn closed_or_failed() {
+        let mut closed = io::BufReader::new(io::empty());
+        assert!(!wait_data(&mut closed).unwrap());
+
+        let mut data = io::BufReader::new(&[1u8, 0][..]);
+        assert!(wait_data(&mut data).unwrap());
+
+        struct Broken;
+        impl io::Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::ConnectionReset.into())
             }
-        });
+        }
+        let error = wait_data(&mut io::BufReader::new(Broken)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionReset);
+    }
+
+    #[test]
+    fn reap_finished() {
+        let (tx, rx) = mpsc::channel::<()>();
+        let mut workers = vec![
+            thread::spawn(|| {}),
+            thread::spawn(move || rx.recv().unwrap_or_default()),
+        ];
+        while !workers[0].is_finished() {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        reap(&mut workers);
+        assert_eq!(workers.len(), 1);
+        drop(tx);
+        workers.pop().unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn port_from_env() {
+        // The only test touching the environment, so it cannot race with others
+        std::env::set_var("TCPMT_PORT", "2000");
+        assert_eq!(Args::try_parse_from(["tcpmtser"]).unwrap().port, 2000);
+        let args = Args::try_parse_from(["tcpmtser", "1024"]).unwrap();
+        assert_eq!(args.port, 1024);
+
+        for wrong in ["0", "65536", "http"] {
+            std::env::set_var("TCPMT_PORT", wrong);
+            assert!(Args::try_parse_from(["tcpmtser"]).is_err());
+        }
+
+        std::env::remove_var("TCPMT_PORT");
+        assert!(Args::try_parse_from(["tcpmtser"]).is_err());
+    }
+
+    #[test]
+    fn unmapped_addresses() {
+        let mapped: SocketAddr = "[::ffff:127.0.0.1]:2000".parse().unwrap();
+        assert_eq!(unmap(mapped).to_string(), "127.0.0.1:2000");
+
+        let v6: SocketAddr = "[2001:db8::1]:2000".parse().unwrap();
+        assert_eq!(unmap(v6), v6);
+        let v4: SocketAddr = "10.0.0.1:2000".parse().unwrap();
+        assert_eq!(unmap(v4), v4);
+    }
+
+    #[test]
+    fn buffer_size() {
+        let args = Args::try_parse_from(["tcpmtser", "1024"]).unwrap();
+        assert_eq!(args.buffer_size, 2048);
+        let args = Args::try_parse_from(["tcpmtser", "1024", "--buffer-size", "64"]).unwrap();
+        assert_eq!(args.buffer_size, 64);
+        assert!(Args::try_parse_from(["tcpmtser", "1024", "--buffer-size", "63"]).is_err());
+    }
+
+    #[test]
+    fn checksum_needs_framing() {
+        assert!(Args::try_parse_from(["tcpmtser", "1024", "--require-checksum"]).is_err());
+        let args =
+            Args::try_parse_from(["tcpmtser", "1024", "--require-checksum", "--framed"]).unwrap();
+        assert!(args.require_checksum);
+    }
+
+    #[test]
+    fn backlog() {
+        let args = Args::try_parse_from(["tcpmtser", "1024"]).unwrap();
+        assert_eq!(args.backlog, 128);
+        let args = Args::try_parse_from(["tcpmtser", "1024", "--backlog", "16"]).unwrap();
+        assert_eq!(args.backlog, 16);
+        assert!(Args::try_parse_from(["tcpmtser", "1024", "--backlog", "0"]).is_err());
     }
 }
@@ -0,0 +1,601 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    operation::OperationError,
+    tlv::{Tlv, TlvError, TlvType},
+    Answer, AnswerOrder, FloatOperation, Lang, Operation, TCPLibError, TlvIterator,
+};
+
+/// Length of the encoded checksum TLV
+const CHECKSUM_LEN: usize = 4;
+
+/// CRC-16/CCITT-FALSE of `bytes`
+pub fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0xffff, |crc, &byte| {
+        (0..8).fold(crc ^ (byte as u16) << 8, |crc, _| match crc & 0x8000 {
+            0 => crc << 1,
+            _ => crc << 1 ^ 0x1021,
+        })
+    })
+}
+
+/// Appends a checksum TLV with the CRC-16 of `frame`
+pub fn append_checksum(frame: &[u8]) -> Box<[u8]> {
+    let checksum = Tlv::new(TlvType::Checksum, &crc16(frame).to_be_bytes())
+        .unwrap()
+        .encode();
+    [frame, &checksum].concat().into()
+}
+
+/// Checks that `frame` ends with a checksum TLV matching the preceding bytes
+pub fn verify_checksum(frame: &[u8]) -> Result<(), TCPLibError> {
+    match frame
+        .len()
+        .checked_sub(CHECKSUM_LEN)
+        .map(|at| frame.split_at(at))
+    {
+        Some((payload, &[tag, 2, high, low]))
+            if tag == TlvType::Checksum.into()
+                && crc16(payload) == u16::from_be_bytes([high, low]) =>
+        {
+            Ok(())
+        }
+        _ => Err(TCPLibError::ChecksumMismatch),
+    }
+}
+
+/// Prefixes `bytes` with their length as a 2-byte big-endian number, so
+/// that the reader knows how many to consume.
+///
+/// # Panics
+///
+/// If `bytes` is longer than `u16::MAX`.
+pub fn frame(bytes: &[u8]) -> Vec<u8> {
+    let len = u16::try_from(bytes.len()).expect("message too long to be framed");
+    [&len.to_be_bytes()[..], bytes].concat()
+}
+
+/// Reads a message prefixed with its length by [`frame`]
+pub fn deframe<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 2];
+    r.read_exact(&mut len)?;
+    let mut message = vec![0u8; u16::from_be_bytes(len) as usize];
+    r.read_exact(&mut message)?;
+    Ok(message)
+}
+
+/// Reads a whole TLV into `buffer`, even if it arrives in several segments,
+/// returning its length. A closed connection is reported as an error.
+pub fn read_tlv<R: Read>(r: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    r.read_exact(&mut buffer[..2])?;
+    let len = 2 + buffer[1] as usize;
+    r.read_exact(&mut buffer[2..len])?;
+    Ok(len)
+}
+
+/// How long to wait for the server to announce its protocol version, which
+/// older servers do not do
+pub const VERSION_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Reads the protocol version announced by the server, if it sends one within
+/// [`VERSION_TIMEOUT`] of connecting. If `framed`, the version comes prefixed
+/// with its length, as written by [`frame`].
+pub fn read_version(reader: &mut BufReader<TcpStream>, framed: bool) -> io::Result<Option<u8>> {
+    reader.get_ref().set_read_timeout(Some(VERSION_TIMEOUT))?;
+    let announced = match reader.fill_buf() {
+        Ok(_) => true,
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            false
+        }
+        Err(e) => return Err(e),
+    };
+    reader.get_ref().set_read_timeout(None)?;
+    if !announced {
+        return Ok(None);
+    }
+
+    let version = match framed {
+        true => deframe(reader)?,
+        false => {
+            let mut buffer = [0u8; 257];
+            let len = read_tlv(reader, &mut buffer)?;
+            buffer[..len].to_vec()
+        }
+    };
+    match version[..] {
+        [tag, 1, version] if tag == u8::from(TlvType::Version) => Ok(Some(version)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the server sent something other than its protocol version",
+        )),
+    }
+}
+
+/// Longest time [`write_frame`] keeps retrying a blocked write
+const WRITE_DEADLINE: Duration = Duration::from_secs(5);
+
+/// Writes all of `bytes` to `w` and flushes it, retrying with a growing
+/// pause while the write would block. Fails with [`io::ErrorKind::TimedOut`]
+/// if it is still blocked after a few seconds.
+pub fn write_frame<W: Write>(w: &mut W, bytes: &[u8]) -> io::Result<()> {
+    write_with_deadline(w, bytes, WRITE_DEADLINE)
+}
+
+fn write_with_deadline<W: Write>(w: &mut W, bytes: &[u8], deadline: Duration) -> io::Result<()> {
+    let start = Instant::now();
+    let mut pause = Duration::from_millis(1);
+    let mut written = 0;
+    loop {
+        let result = match written < bytes.len() {
+            true => w.write(&bytes[written..]),
+            false => w.flush().map(|_| 0),
+        };
+        match result {
+            Ok(0) if written < bytes.len() => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(0) => return Ok(()),
+            Ok(n) => written += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                if start.elapsed() >= deadline {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "the peer is not reading what we send",
+                    ));
+                }
+                thread::sleep(pause);
+                pause = (pause * 2).min(Duration::from_millis(100));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// A set of operations sent together
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Frame(pub Vec<Operation>);
+
+impl Frame {
+    pub fn encode(self) -> Box<[u8]> {
+        let mut encoded = Vec::with_capacity(self.0.iter().map(Operation::encoded_len).sum());
+        for operation in &self.0 {
+            // Writing to a vector cannot fail
+            operation.write_to(&mut encoded).unwrap();
+        }
+        encoded.into()
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for Frame {
+    type Error = OperationError;
+
+    fn try_from(bytes: &'a [u8]) -> Result<Self, Self::Error> {
+        Ok(Frame(
+            TlvIterator::process(bytes)
+                .operations()
+                .collect::<Result<_, _>>()?,
+        ))
+    }
+}
+
+impl From<Vec<Operation>> for Frame {
+    fn from(operations: Vec<Operation>) -> Self {
+        Frame(operations)
+    }
+}
+
+impl IntoIterator for Frame {
+    type Item = Operation;
+    type IntoIter = std::vec::IntoIter<Operation>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Frame {
+    type Item = &'a Operation;
+    type IntoIter = std::slice::Iter<'a, Operation>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+/// Adds up the results of the operations in `buf`, the data of an
+/// [`TlvType::Evaluate`] TLV
+pub fn evaluate(buf: &[u8]) -> Result<i64, OperationError> {
+    TlvIterator::process(buf)
+        .operations()
+        .try_fold(0i64, |sum, operation| {
+            Ok(sum.saturating_add(operation?.reduce()?))
+        })
+}
+
+/// Computes `operation`, adding its result to `acc`, and returns the answer,
+/// with the error message in `lang`. `on_op` is called with the operation and
+/// its result if it succeeds.
+pub fn compute_operation(
+    operation: Result<Operation, OperationError>,
+    acc: &mut i64,
+    lang: Lang,
+    on_op: impl FnOnce(&Operation, i64),
+) -> Answer {
+    match operation.and_then(|operation| operation.reduce().map(|res| (operation, res))) {
+        Ok((operation, result)) => {
+            *acc = acc.saturating_add(result);
+            on_op(&operation, result);
+            Answer::ok(*acc).with_result(result)
+        }
+        Err(e) => Answer::error(*acc, e.localized(lang)),
+    }
+}
+
+/// Computes the operations in `buf`, adding their results to `acc`, and
+/// returns the answer to each of them. `on_op` is called with every
+/// operation successfully computed and its result. Control TLVs, such as
+/// padding, checksums and keepalives, are skipped. Evaluate TLVs are answered
+/// with the sum of the operations they contain as their result, and
+/// floating-point operations with their own result, both leaving `acc`
+/// untouched. Error messages are written in `lang`.
+pub fn compute_frame(
+    buf: &[u8],
+    acc: &mut i64,
+    lang: Lang,
+    mut on_op: impl FnMut(&Operation, i64),
+) -> Vec<Answer> {
+    TlvIterator::process(buf)
+        .filter(|tlv| !tlv.tag.is_control())
+        .map(|tlv| {
+            if tlv.tag == TlvType::Evaluate {
+                return match evaluate(tlv.data) {
+                    Ok(sum) => Answer::ok(*acc).with_result(sum),
+                    Err(e) => Answer::error(*acc, e.localized(lang)),
+                };
+            }
+            if matches!(
+                tlv.tag,
+                TlvType::SumF | TlvType::SubF | TlvType::MulF | TlvType::DivF
+            ) {
+                return match FloatOperation::try_from(tlv).and_then(|op| op.reduce()) {
+                    Ok(result) => Answer::ok(*acc).with_float(result),
+                    Err(e) => Answer::error(*acc, e.localized(lang)),
+                };
+            }
+            compute_operation(Operation::try_from(tlv), acc, lang, &mut on_op)
+        })
+        .collect()
+}
+
+/// Like [`compute_frame`], but returning the encoded answers
+pub fn process_frame(
+    buf: &[u8],
+    acc: &mut i64,
+    order: AnswerOrder,
+    lang: Lang,
+    on_op: impl FnMut(&Operation, i64),
+) -> Result<Box<[u8]>, TlvError> {
+    let mut encoded = Vec::new();
+    for answer in compute_frame(buf, acc, lang, on_op) {
+        encoded.extend_from_slice(&answer.encode(order)?);
+    }
+    Ok(encoded.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, BufReader, Write},
+        net::{Ipv4Addr, TcpListener, TcpStream},
+        thread,
+        time::Duration,
+    };
+
+    use crate::{
+        append_checksum, compute_frame, crc16, deframe, frame, process_frame, read_tlv,
+        read_version, verify_checksum, write_frame, Answer, AnswerOrder, FloatOperation, Frame,
+        Lang, Operation, TCPLibError, Tlv, TlvIterator, TlvType, PROTOCOL_VERSION,
+    };
+
+    use super::write_with_deadline;
+
+    /// A writer that blocks `blocks` times and then takes up to 3 bytes each time
+    struct Blocking {
+        blocks: usize,
+        written: Vec<u8>,
+        flushed: bool,
+    }
+
+    impl io::Write for Blocking {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.blocks > 0 {
+                self.blocks -= 1;
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let len = buf.len().min(3);
+            self.written.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushed = true;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_retries() {
+        let mut w = Blocking {
+            blocks: 2,
+            written: vec![],
+            flushed: false,
+        };
+        write_frame(&mut w, &[1, 2, 3, 4, 5, 6, 7]).unwrap();
+        assert_eq!(w.blocks, 0);
+        assert_eq!(w.written, [1, 2, 3, 4, 5, 6, 7]);
+        assert!(w.flushed);
+
+        let mut w = Blocking {
+            blocks: usize::MAX,
+            written: vec![],
+            flushed: false,
+        };
+        let error = write_with_deadline(&mut w, &[1, 2], Duration::from_millis(20)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(!w.flushed);
+    }
+
+    /// Connects to a server that writes `greeting` as soon as it accepts the
+    /// connection and reads the version it announces
+    fn version_from(greeting: &'static [u8], framed: bool) -> io::Result<Option<u8>> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(greeting).unwrap();
+            stream
+        });
+        let mut reader = BufReader::new(TcpStream::connect(address).unwrap());
+        let version = read_version(&mut reader, framed);
+        drop(server.join().unwrap());
+        version
+    }
+
+    #[test]
+    fn version_handshake() {
+        assert_eq!(
+            version_from(&[40, 1, PROTOCOL_VERSION], false).unwrap(),
+            Some(PROTOCOL_VERSION)
+        );
+        assert_eq!(version_from(&[0, 3, 40, 1, 7], true).unwrap(), Some(7));
+        assert_eq!(version_from(&[], false).unwrap(), None);
+        assert_eq!(
+            version_from(&[1, 2, 3, 4], false).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn whole_tlv() {
+        let mut buffer = [0u8; 257];
+        let mut reader = &[1u8, 2, 3, 4, 6, 1][..];
+        assert_eq!(read_tlv(&mut reader, &mut buffer).unwrap(), 4);
+        assert_eq!(buffer[..4], [1, 2, 3, 4]);
+        assert!(read_tlv(&mut reader, &mut buffer).is_err());
+    }
+
+    #[test]
+    fn process_two_operations() {
+        let frame = Frame::from(vec![
+            Operation::Sum((3, 4).into()),
+            Operation::Mul((2, 5).into()),
+        ])
+        .encode();
+        let mut acc = 1;
+        let mut seen = vec![];
+        let answers = process_frame(
+            &frame,
+            &mut acc,
+            AnswerOrder::MessageFirst,
+            Lang::En,
+            |op, res| seen.push((op.clone(), res)),
+        )
+        .unwrap();
+
+        assert_eq!(acc, 18);
+        assert_eq!(
+            seen,
+            [
+                (Operation::Sum((3, 4).into()), 7),
+                (Operation::Mul((2, 5).into()), 10)
+            ]
+        );
+        let accs: Vec<_> = TlvIterator::process(&answers)
+            .map(|tlv| Answer::try_from(tlv).unwrap().acc.0)
+            .collect();
+        assert_eq!(accs, [8, 18]);
+    }
+
+    #[test]
+    fn process_padding() {
+        let frame = [1u8, 2, 3, 4, 0, 0, 0, 0, 1, 2, 1, 1];
+        let mut acc = 0;
+        let answers = process_frame(
+            &frame,
+            &mut acc,
+            AnswerOrder::MessageFirst,
+            Lang::En,
+            |_, _| {},
+        )
+        .unwrap();
+        let answers: Vec<_> = TlvIterator::process(&answers)
+            .map(|tlv| Answer::try_from(tlv).unwrap())
+            .collect();
+        assert_eq!(
+            answers,
+            [Answer::ok(7).with_result(7), Answer::ok(9).with_result(2)]
+        );
+    }
+
+    #[test]
+    fn process_wrong_operation() {
+        let frame = Frame::from(vec![Operation::Div((3, 0).into())]).encode();
+        let mut acc = 1;
+        let answers = process_frame(
+            &frame,
+            &mut acc,
+            AnswerOrder::MessageFirst,
+            Lang::En,
+            |_, _| panic!("No operation should have been computed"),
+        )
+        .unwrap();
+
+        let answer = Answer::try_from(Tlv::try_from(&answers[..]).unwrap()).unwrap();
+        assert_eq!(answer.acc.0, 1);
+        assert!(answer.message.is_some());
+    }
+
+    #[test]
+    fn process_evaluate() {
+        let operations = Frame::from(vec![
+            Operation::Sum((3, 4).into()),
+            Operation::Mul((5, 2).into()),
+        ])
+        .encode();
+        let evaluate = Tlv::new(TlvType::Evaluate, &operations).unwrap().encode();
+        let frame = [&evaluate[..], &Operation::Sum((1, 1).into()).encode()].concat();
+
+        let mut acc = 100;
+        let answers = compute_frame(&frame, &mut acc, Lang::En, |_, _| {});
+        assert_eq!(
+            answers,
+            [
+                Answer::ok(100).with_result(17),
+                Answer::ok(102).with_result(2)
+            ]
+        );
+        assert_eq!(acc, 102);
+
+        let wrong = [4u8, 2, 3, 0];
+        let evaluate = Tlv::new(TlvType::Evaluate, &wrong).unwrap().encode();
+        let answers = compute_frame(&evaluate, &mut acc, Lang::En, |_, _| {});
+        assert!(answers[0].message.is_some());
+    }
+
+    #[test]
+    fn process_float() {
+        let frame = [
+            &FloatOperation::Mul((3.5, 2.0).into()).encode()[..],
+            &FloatOperation::Div((1.0, 0.0).into()).encode(),
+        ]
+        .concat();
+
+        let mut acc = 5;
+        let answers = compute_frame(&frame, &mut acc, Lang::En, |_, _| {});
+        assert_eq!(answers[0], Answer::ok(5).with_float(7.0));
+        assert!(answers[1].message.is_some());
+        assert_eq!(acc, 5);
+    }
+
+    #[test]
+    fn framed_answer() {
+        let answer = Answer::error(-3, "Wrong domain");
+        let encoded = answer.clone().encode(AnswerOrder::MessageFirst).unwrap();
+        let framed = frame(&encoded);
+        assert_eq!(framed[..2], (encoded.len() as u16).to_be_bytes());
+
+        // The reader stops at the end of each message
+        let reader = [&framed[..], &frame(&[])[..], &[1u8, 2][..]].concat();
+        let mut reader = &reader[..];
+        let message = deframe(&mut reader).unwrap();
+        assert_eq!(
+            Answer::try_from(Tlv::try_from(&message[..]).unwrap()).unwrap(),
+            answer
+        );
+        assert!(deframe(&mut reader).unwrap().is_empty());
+        assert!(deframe(&mut reader).is_err());
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+    }
+
+    #[test]
+    fn checksum_matching() {
+        let frame = Frame::from(vec![Operation::Sum((3, 4).into())]).encode();
+        let checked = append_checksum(&frame);
+        assert_eq!(checked[..frame.len()], frame[..]);
+        assert_eq!(checked[frame.len()..frame.len() + 2], [20u8, 2]);
+        assert!(verify_checksum(&checked).is_ok());
+    }
+
+    #[test]
+    fn checksum_corrupted() {
+        let mut checked =
+            append_checksum(&Frame::from(vec![Operation::Sum((3, 4).into())]).encode());
+        checked[2] ^= 0x10;
+        assert!(matches!(
+            verify_checksum(&checked),
+            Err(TCPLibError::ChecksumMismatch)
+        ));
+        assert!(verify_checksum(&checked[..2]).is_err());
+        assert!(verify_checksum(&Frame::from(vec![]).encode()).is_err());
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        let operations = vec![
+            Operation::Sum((3, 4).into()),
+            Operation::Fact(5.into()),
+            Operation::Div((-10, 2).into()),
+        ];
+        let encoded = Frame::from(operations.clone()).encode();
+        assert_eq!(encoded[..], [1u8, 2, 3, 4, 6, 1, 5, 4, 2, 246, 2]);
+
+        let frame = Frame::try_from(&encoded[..]).unwrap();
+        assert_eq!(frame.into_iter().collect::<Vec<_>>(), operations);
+    }
+
+    #[test]
+    fn frame_padding() {
+        let frame = Frame::try_from(&[0u8, 0, 1, 2, 3, 4, 0, 0][..]).unwrap();
+        assert_eq!(frame, Frame::from(vec![Operation::Sum((3, 4).into())]));
+    }
+
+    #[test]
+    fn frame_wrong_operation() {
+        assert!(Frame::try_from(&[1u8, 2, 3, 4, 10, 1, 5][..]).is_err());
+    }
+}
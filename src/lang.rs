@@ -0,0 +1,174 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+use std::{fmt::Display, str::FromStr};
+
+use crate::operation::OperationError;
+
+/// Language of the error messages sent to the clients
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Lang {
+    #[default]
+    En,
+    Gl,
+    Es,
+}
+
+impl FromStr for Lang {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "en" => Ok(Lang::En),
+            "gl" => Ok(Lang::Gl),
+            "es" => Ok(Lang::Es),
+            _ => Err(format!("unknown language {s}, use en, gl or es")),
+        }
+    }
+}
+
+impl Display for Lang {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Lang::En => "en",
+            Lang::Gl => "gl",
+            Lang::Es => "es",
+        })
+    }
+}
+
+/// Error messages in English, Galician and Spanish, in the order of the
+/// variants of [`OperationError`]. `{}` stands for the value of the variant.
+const MESSAGES: [[&str; 3]; 12] = [
+    [
+        "Unsupported operation {}",
+        "Operación non soportada {}",
+        "Operación no soportada {}",
+    ],
+    [
+        "Could not parse operation",
+        "Non se puido interpretar a operación",
+        "No se pudo interpretar la operación",
+    ],
+    [
+        "Not enough data in TLV",
+        "Non hai datos dabondo no TLV",
+        "No hay suficientes datos en el TLV",
+    ],
+    [
+        "Invalid parameter",
+        "Parámetro non válido",
+        "Parámetro no válido",
+    ],
+    [
+        "Could not parse integer",
+        "Non se puido interpretar o enteiro",
+        "No se pudo interpretar el entero",
+    ],
+    [
+        "{} doesn't fit in a single byte",
+        "{} non cabe nun só byte",
+        "{} no cabe en un solo byte",
+    ],
+    [
+        "Could not parse decimal number",
+        "Non se puido interpretar o número decimal",
+        "No se pudo interpretar el número decimal",
+    ],
+    [
+        "Could not parse TLV",
+        "Non se puido interpretar o TLV",
+        "No se pudo interpretar el TLV",
+    ],
+    [
+        "Result is out of range",
+        "O resultado está fóra de rango",
+        "El resultado está fuera de rango",
+    ],
+    ["Wrong domain", "Dominio incorrecto", "Dominio incorrecto"],
+    [
+        "Division overflow",
+        "Desbordamento na división",
+        "Desbordamiento en la división",
+    ],
+    ["Something wrong", "Algo foi mal", "Algo salió mal"],
+];
+
+impl OperationError {
+    /// The message of the error in `lang`
+    pub fn localized(&self, lang: Lang) -> String {
+        let (index, value) = match self {
+            OperationError::UnsupportedOperation(op) => (0, op.to_string()),
+            OperationError::Parse => (1, String::new()),
+            OperationError::NotEnoughData(_) => (2, String::new()),
+            OperationError::InvalidParameter(_) => (3, String::new()),
+            OperationError::ParseIntError(_) => (4, String::new()),
+            OperationError::OperandOutOfRange { value } => (5, value.to_string()),
+            OperationError::ParseFloatError(_) => (6, String::new()),
+            OperationError::ParseTlvError(_) => (7, String::new()),
+            OperationError::OverFlow => (8, String::new()),
+            OperationError::WrongDomain => (9, String::new()),
+            OperationError::DivOverflow => (10, String::new()),
+            OperationError::Generic => (11, String::new()),
+        };
+        MESSAGES[index][lang as usize].replace("{}", &value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{operation::OperationError, Lang, Operation};
+
+    #[test]
+    fn divide_by_zero() {
+        let error = Operation::Div((3, 0).into()).reduce().unwrap_err();
+        assert_eq!(error.localized(Lang::Gl), "Dominio incorrecto");
+        assert_eq!(error.localized(Lang::En), error.to_string());
+    }
+
+    #[test]
+    fn english_matches_display() {
+        let errors = [
+            OperationError::UnsupportedOperation("^".into()),
+            OperationError::Parse,
+            OperationError::OperandOutOfRange { value: 200 },
+            OperationError::OverFlow,
+            OperationError::DivOverflow,
+            OperationError::Generic,
+            "x".parse::<i8>().unwrap_err().into(),
+        ];
+        for error in errors {
+            assert_eq!(error.localized(Lang::En), error.to_string());
+        }
+        assert_eq!(
+            OperationError::OperandOutOfRange { value: 200 }.localized(Lang::Es),
+            "200 no cabe en un solo byte"
+        );
+    }
+
+    #[test]
+    fn parse_lang() {
+        assert_eq!("GL".parse::<Lang>().unwrap(), Lang::Gl);
+        assert_eq!("es".parse::<Lang>().unwrap().to_string(), "es");
+        assert!("fr".parse::<Lang>().is_err());
+    }
+}
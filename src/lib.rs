@@ -29,32 +29,107 @@ use tlv::TlvError;
 use thiserror::Error;
 
 mod answer;
+mod frame;
+mod lang;
+mod log;
 mod operation;
+mod rng;
+mod server;
 mod tlv;
 
-pub use answer::{Answer, AnswerOrder};
-pub use operation::Operation;
+pub use answer::{Answer, AnswerOrder, Numberf64, Numberi64};
+pub use frame::{
+    append_checksum, compute_frame, compute_operation, crc16, deframe, evaluate, frame,
+    process_frame, read_tlv, read_version, verify_checksum, write_frame, Frame, VERSION_TIMEOUT,
+};
+pub use lang::Lang;
+pub use log::{Logger, Verbosity};
+pub use operation::{ArithMode, FloatOperation, Operation};
+pub use rng::XorShift;
+pub use server::{count_operation, reply_frame, stats_summary, Reply, ReplyOptions, Stats};
+pub use tlv::RawTlvIterator;
 pub use tlv::Tlv;
+pub use tlv::TlvBuilder;
 pub use tlv::TlvIterator;
+pub use tlv::TlvType;
+pub use tlv::{hexdump, hexdump_annotated};
+
+/// Version of the protocol spoken by this library, sent by the server in a
+/// [`TlvType::Version`] TLV as soon as a client connects
+pub const PROTOCOL_VERSION: u8 = 1;
 
 #[derive(Clone, Error, Debug)]
 pub enum TCPLibError {
-    #[error("Operation error")]
+    #[error("Operation error: {0}")]
     OperationError(#[from] OperationError),
     #[error("Unsupported operation {0}")]
     UnsupportedOperation(String),
     #[error("Could not parse operation")]
     Parse,
-    #[error("Not enough data in TLV")]
+    #[error("Not enough data in TLV: {0}")]
     NotEnoughData(#[from] TryFromSliceError),
-    #[error("Invalid parameter")]
+    #[error("Invalid parameter: {0}")]
     InvalidParameter(#[from] TryFromIntError),
-    #[error("Could not parse integer")]
+    #[error("Could not parse integer: {0}")]
     ParseIntError(#[from] ParseIntError),
-    #[error("Wrong encoding for error message")]
+    #[error("Wrong encoding for error message: {0}")]
     ParseStringError(#[from] Utf8Error),
-    #[error("Could not parse TLV")]
+    #[error("Could not parse TLV: {0}")]
     ParseTlvError(#[from] TlvError),
-    #[error("Something wrong")]
-    Generic,
+    #[error("Message in the wrong place of the answer")]
+    WrongAnswerOrder,
+    #[error("Missing or wrong checksum")]
+    ChecksumMismatch,
+    #[error("Repeated {0:?} TLV in the answer")]
+    DuplicateTlv(TlvType),
+    #[error("An answer needs at least one value")]
+    EmptyAnswer,
+    /// A TLV that cannot be decoded as the type named in the context
+    #[error("Wrong TLV for {0}")]
+    Generic(&'static str),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use crate::{Answer, Numberi64, Operation, TCPLibError, Tlv};
+
+    #[test]
+    fn error_sources() {
+        let errors = [
+            Numberi64::try_from(Tlv::try_from(&[16u8, 1, 0][..]).unwrap()).unwrap_err(),
+            Answer::try_from(Tlv::try_from(&[10u8, 3, 11, 1, 0xff][..]).unwrap()).unwrap_err(),
+            TCPLibError::from("200+1".parse::<Operation>().unwrap_err()),
+            TCPLibError::from(<[u8; 2]>::try_from(&[0u8][..]).unwrap_err()),
+            TCPLibError::from(u8::try_from(256).unwrap_err()),
+            TCPLibError::from("x".parse::<i8>().unwrap_err()),
+        ];
+        for error in errors {
+            assert!(error.source().is_some(), "{error:?} has no source");
+        }
+    }
+
+    #[test]
+    fn error_messages() {
+        let tlv = Tlv::try_from(&[1u8, 2, 3, 4][..]).unwrap();
+        assert_eq!(
+            Answer::try_from(Tlv::try_from(&[10u8, 0][..]).unwrap())
+                .unwrap_err()
+                .to_string(),
+            "Wrong TLV for Answer"
+        );
+        assert_eq!(
+            Numberi64::try_from(&tlv).unwrap_err().to_string(),
+            "Wrong TLV for Numberi64"
+        );
+        assert_eq!(
+            TCPLibError::from(<[u8; 2]>::try_from(&[0u8][..]).unwrap_err()).to_string(),
+            "Not enough data in TLV: could not convert slice to array"
+        );
+        assert_eq!(
+            TCPLibError::from("x".parse::<i8>().unwrap_err()).to_string(),
+            "Could not parse integer: invalid digit found in string"
+        );
+    }
 }
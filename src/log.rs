@@ -0,0 +1,118 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+use std::{
+    fmt::Arguments,
+    io::{stderr, stdout, Write},
+};
+
+/// How much information the programs print
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+    Debug,
+}
+
+impl Verbosity {
+    /// Verbosity level from the number of `-v` flags and the `-q` flag
+    pub fn from_flags(verbose: u8, quiet: bool) -> Self {
+        match (quiet, verbose) {
+            (true, _) => Verbosity::Quiet,
+            (false, 0) => Verbosity::Normal,
+            (false, 1) => Verbosity::Verbose,
+            (false, _) => Verbosity::Debug,
+        }
+    }
+}
+
+/// A tiny logger that prints messages depending on the verbosity level
+#[derive(Clone, Copy, Debug)]
+pub struct Logger {
+    verbosity: Verbosity,
+}
+
+impl Logger {
+    pub fn new(verbosity: Verbosity) -> Self {
+        Self { verbosity }
+    }
+
+    /// Writes a message to `out` only if the logger verbosity is at least `level`
+    pub fn log_to<W: Write>(&self, out: &mut W, level: Verbosity, args: Arguments) {
+        if self.verbosity >= level {
+            // Logging must never make the programs fail
+            let _ = writeln!(out, "{}", args);
+        }
+    }
+
+    /// Errors, printed to stderr unless in quiet mode
+    pub fn error(&self, args: Arguments) {
+        self.log_to(&mut stderr(), Verbosity::Normal, args)
+    }
+
+    /// Regular output, printed to stdout unless in quiet mode
+    pub fn print(&self, args: Arguments) {
+        self.log_to(&mut stdout(), Verbosity::Normal, args)
+    }
+
+    /// Details, only printed with `-v`
+    pub fn verbose(&self, args: Arguments) {
+        self.log_to(&mut stdout(), Verbosity::Verbose, args)
+    }
+
+    /// Raw protocol data, only printed with `-vv`
+    pub fn debug(&self, args: Arguments) {
+        self.log_to(&mut stdout(), Verbosity::Debug, args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Logger, Verbosity};
+
+    #[test]
+    fn verbosity_from_flags() {
+        assert_eq!(Verbosity::from_flags(0, false), Verbosity::Normal);
+        assert_eq!(Verbosity::from_flags(1, false), Verbosity::Verbose);
+        assert_eq!(Verbosity::from_flags(5, false), Verbosity::Debug);
+        assert_eq!(Verbosity::from_flags(2, true), Verbosity::Quiet);
+    }
+
+    #[test]
+    fn quiet_operation() {
+        let mut out = vec![];
+        let logger = Logger::new(Verbosity::Quiet);
+        logger.log_to(&mut out, Verbosity::Normal, format_args!("Accumulator: 7"));
+        logger.log_to(&mut out, Verbosity::Verbose, format_args!("3+4 = 7"));
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn verbose_operation() {
+        let mut out = vec![];
+        let logger = Logger::new(Verbosity::Verbose);
+        logger.log_to(&mut out, Verbosity::Verbose, format_args!("3+4 = 7"));
+        logger.log_to(&mut out, Verbosity::Debug, format_args!("01 02 03 04"));
+        assert_eq!(out, b"3+4 = 7\n");
+    }
+}
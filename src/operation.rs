@@ -23,16 +23,20 @@
 use std::{
     array::TryFromSliceError,
     fmt::Display,
-    num::{ParseIntError, TryFromIntError},
+    io::{self, Write},
+    num::{ParseFloatError, ParseIntError, TryFromIntError},
     str::FromStr,
 };
 
-use regex::Regex;
 use thiserror::Error;
 
-use crate::{tlv::TlvType, Tlv};
+use crate::{
+    tlv::{TlvError, TlvType},
+    Tlv,
+};
 
 #[derive(Clone, Error, Debug)]
+#[non_exhaustive]
 pub enum OperationError {
     #[error("Unsupported operation {0}")]
     UnsupportedOperation(Box<str>),
@@ -44,27 +48,37 @@ pub enum OperationError {
     InvalidParameter(#[from] TryFromIntError),
     #[error("Could not parse integer")]
     ParseIntError(#[from] ParseIntError),
+    #[error("{value} doesn't fit in a single byte")]
+    OperandOutOfRange { value: i64 },
+    #[error("Could not parse decimal number")]
+    ParseFloatError(#[from] ParseFloatError),
+    #[error("Could not parse TLV")]
+    ParseTlvError(#[from] TlvError),
     #[error("Result is out of range")]
     OverFlow,
     #[error("Wrong domain")]
     WrongDomain,
+    #[error("Division overflow")]
+    DivOverflow,
     #[error("Something wrong")]
     Generic,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct BinomialOperationData<T1, T2>(T1, T2);
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct MonomialOperationData<T1>(T1);
 
-impl<T1, T2> BinomialOperationData<T1, T2>
-where
-    T1: Into<i8> + Copy,
-    T2: Into<i8> + Copy,
-{
-    pub fn encode(&self) -> [u8; 2] {
-        [self.0.into() as u8, self.1.into() as u8]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TrinomialOperationData<T1, T2, T3>(T1, T2, T3);
+
+impl BinomialOperationData<i8, i8> {
+    /// Length of the encoded operands
+    pub const WIRE_LEN: usize = 2;
+
+    pub fn encode(&self) -> [u8; Self::WIRE_LEN] {
+        [self.0 as u8, self.1 as u8]
     }
 }
 
@@ -72,17 +86,116 @@ impl<T1> MonomialOperationData<T1>
 where
     T1: Into<i8> + Copy,
 {
+    /// Length of the encoded operand
+    pub const WIRE_LEN: usize = 1;
+
     pub fn encode(&self) -> [u8; 1] {
         self.0.into().to_be_bytes()
     }
 }
 
+impl TrinomialOperationData<i8, i8, i8> {
+    /// Length of the encoded operands
+    pub const WIRE_LEN: usize = 3;
+
+    pub fn encode(&self) -> [u8; Self::WIRE_LEN] {
+        [self.0 as u8, self.1 as u8, self.2 as u8]
+    }
+}
+
+impl BinomialOperationData<u8, u8> {
+    /// Length of the encoded operands
+    pub const WIRE_LEN: usize = 2;
+
+    pub fn encode(&self) -> [u8; Self::WIRE_LEN] {
+        [self.0, self.1]
+    }
+}
+
+impl BinomialOperationData<i16, i16> {
+    /// Length of the encoded operands
+    pub const WIRE_LEN: usize = 4;
+
+    pub fn encode(&self) -> [u8; Self::WIRE_LEN] {
+        let mut data = [0u8; Self::WIRE_LEN];
+        data[..2].copy_from_slice(&self.0.to_be_bytes());
+        data[2..].copy_from_slice(&self.1.to_be_bytes());
+        data
+    }
+}
+
+impl BinomialOperationData<f32, f32> {
+    /// Length of the encoded operands
+    pub const WIRE_LEN: usize = 8;
+
+    pub fn encode(&self) -> [u8; Self::WIRE_LEN] {
+        let mut data = [0u8; Self::WIRE_LEN];
+        data[..4].copy_from_slice(&self.0.to_be_bytes());
+        data[4..].copy_from_slice(&self.1.to_be_bytes());
+        data
+    }
+}
+
 impl From<[u8; 2]> for BinomialOperationData<i8, i8> {
     fn from(value: [u8; 2]) -> Self {
         (i8::from_be_bytes([value[0]]), i8::from_be_bytes([value[1]])).into()
     }
 }
 
+impl From<[u8; 2]> for BinomialOperationData<u8, u8> {
+    fn from(value: [u8; 2]) -> Self {
+        Self(value[0], value[1])
+    }
+}
+
+impl From<(u8, u8)> for BinomialOperationData<u8, u8> {
+    fn from((a, b): (u8, u8)) -> Self {
+        Self(a, b)
+    }
+}
+
+impl From<[u8; 4]> for BinomialOperationData<i16, i16> {
+    fn from(value: [u8; 4]) -> Self {
+        Self(
+            i16::from_be_bytes([value[0], value[1]]),
+            i16::from_be_bytes([value[2], value[3]]),
+        )
+    }
+}
+
+impl From<(i16, i16)> for BinomialOperationData<i16, i16> {
+    fn from((a, b): (i16, i16)) -> Self {
+        Self(a, b)
+    }
+}
+
+impl From<[u8; 3]> for TrinomialOperationData<i8, i8, i8> {
+    fn from(value: [u8; 3]) -> Self {
+        Self(value[0] as i8, value[1] as i8, value[2] as i8)
+    }
+}
+
+impl From<(i8, i8, i8)> for TrinomialOperationData<i8, i8, i8> {
+    fn from((a, b, c): (i8, i8, i8)) -> Self {
+        Self(a, b, c)
+    }
+}
+
+impl From<[u8; 8]> for BinomialOperationData<f32, f32> {
+    fn from(value: [u8; 8]) -> Self {
+        Self(
+            f32::from_be_bytes([value[0], value[1], value[2], value[3]]),
+            f32::from_be_bytes([value[4], value[5], value[6], value[7]]),
+        )
+    }
+}
+
+impl From<(f32, f32)> for BinomialOperationData<f32, f32> {
+    fn from((a, b): (f32, f32)) -> Self {
+        Self(a, b)
+    }
+}
+
 impl From<[u8; 1]> for MonomialOperationData<i8> {
     fn from(value: [u8; 1]) -> Self {
         Self(i8::from_be_bytes(value))
@@ -104,7 +217,61 @@ impl From<i8> for MonomialOperationData<i8> {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+/// How to deal with results that do not fit in the intermediate type
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ArithMode {
+    /// Fail with [`OperationError::OverFlow`]
+    #[default]
+    Checked,
+    /// Clamp to the bounds of the intermediate type
+    Saturating,
+    /// Wrap around the bounds of the intermediate type
+    Wrapping,
+}
+
+impl ArithMode {
+    fn apply(
+        self,
+        (a, b): (i16, i16),
+        checked: fn(i16, i16) -> Option<i16>,
+        saturating: fn(i16, i16) -> i16,
+        wrapping: fn(i16, i16) -> i16,
+    ) -> Result<i64, OperationError> {
+        Ok(match self {
+            ArithMode::Checked => checked(a, b).ok_or(OperationError::OverFlow)?,
+            ArithMode::Saturating => saturating(a, b),
+            ArithMode::Wrapping => wrapping(a, b),
+        }
+        .into())
+    }
+}
+
+/// `base^exp mod m` by repeated squaring
+fn modpow(base: i64, exp: i64, m: i64) -> Result<i64, OperationError> {
+    if m == 0 || exp < 0 {
+        return Err(OperationError::WrongDomain);
+    }
+
+    let step = |a: i64, b: i64| {
+        a.checked_mul(b)
+            .and_then(|p| p.checked_rem(m))
+            .ok_or(OperationError::OverFlow)
+    };
+    let (mut res, mut base, mut exp) = (step(1, 1)?, step(base, 1)?, exp);
+    while exp > 0 {
+        if exp & 1 == 1 {
+            res = step(res, base)?;
+        }
+        base = step(base, base)?;
+        exp >>= 1;
+    }
+    Ok(res)
+}
+
+/// Operations are ordered by their tag and then by their operands, so the
+/// variants must be declared in the order of their tags.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[non_exhaustive]
 pub enum Operation {
     Sum(BinomialOperationData<i8, i8>),
     Sub(BinomialOperationData<i8, i8>),
@@ -112,79 +279,540 @@ pub enum Operation {
     Div(BinomialOperationData<i8, i8>),
     Rem(BinomialOperationData<i8, i8>),
     Fact(MonomialOperationData<i8>),
+    Min(BinomialOperationData<i8, i8>),
+    Max(BinomialOperationData<i8, i8>),
+    /// Remainder of the division, always non-negative
+    Mod(BinomialOperationData<i8, i8>),
+    And(BinomialOperationData<i8, i8>),
+    Or(BinomialOperationData<i8, i8>),
+    Xor(BinomialOperationData<i8, i8>),
+    Shl(BinomialOperationData<i8, i8>),
+    Shr(BinomialOperationData<i8, i8>),
+    Isqrt(MonomialOperationData<i8>),
+    Ilog2(MonomialOperationData<i8>),
+    ModPow(TrinomialOperationData<i8, i8, i8>),
+    AbsDiff(BinomialOperationData<i8, i8>),
+    /// Number of bits set in the operand
+    PopCount(MonomialOperationData<i8>),
+    /// Mean of both operands, truncated toward zero
+    Avg(BinomialOperationData<i8, i8>),
+    USum(BinomialOperationData<u8, u8>),
+    USub(BinomialOperationData<u8, u8>),
+    UMul(BinomialOperationData<u8, u8>),
+    UDiv(BinomialOperationData<u8, u8>),
+    WSum(BinomialOperationData<i16, i16>),
+    WSub(BinomialOperationData<i16, i16>),
+    WMul(BinomialOperationData<i16, i16>),
+    WDiv(BinomialOperationData<i16, i16>),
 }
 
 impl Operation {
+    /// Largest factorial input whose result still fits in an `i64`.
+    pub const MAX_FACTORIAL_INPUT: i8 = 20;
+
+    /// Largest data length of the TLV of any operation
+    pub const MAX_DATA_LEN: usize = Self::wide_len();
+
+    /// Data length of the TLV of operations with two operands
+    pub const fn binomial_len() -> usize {
+        BinomialOperationData::<i8, i8>::WIRE_LEN
+    }
+
+    /// Data length of the TLV of operations with a single operand
+    pub const fn monomial_len() -> usize {
+        MonomialOperationData::<i8>::WIRE_LEN
+    }
+
+    /// Data length of the TLV of operations with three operands
+    pub const fn trinomial_len() -> usize {
+        TrinomialOperationData::<i8, i8, i8>::WIRE_LEN
+    }
+
+    /// Data length of the TLV of operations with two 16 bit operands
+    pub const fn wide_len() -> usize {
+        BinomialOperationData::<i16, i16>::WIRE_LEN
+    }
+
+    /// The operands of the operation. The second one is `None` for
+    /// operations with a single operand, and the modulus of
+    /// [`Operation::ModPow`] is left out.
+    pub fn operands(&self) -> (i64, Option<i64>) {
+        match *self {
+            Operation::Sum(BinomialOperationData(a, b))
+            | Operation::Sub(BinomialOperationData(a, b))
+            | Operation::Mul(BinomialOperationData(a, b))
+            | Operation::Div(BinomialOperationData(a, b))
+            | Operation::Rem(BinomialOperationData(a, b))
+            | Operation::Mod(BinomialOperationData(a, b))
+            | Operation::Min(BinomialOperationData(a, b))
+            | Operation::Max(BinomialOperationData(a, b))
+            | Operation::And(BinomialOperationData(a, b))
+            | Operation::Or(BinomialOperationData(a, b))
+            | Operation::Xor(BinomialOperationData(a, b))
+            | Operation::Shl(BinomialOperationData(a, b))
+            | Operation::Shr(BinomialOperationData(a, b))
+            | Operation::AbsDiff(BinomialOperationData(a, b))
+            | Operation::Avg(BinomialOperationData(a, b))
+            | Operation::ModPow(TrinomialOperationData(a, b, _)) => (a.into(), Some(b.into())),
+            Operation::USum(BinomialOperationData(a, b))
+            | Operation::USub(BinomialOperationData(a, b))
+            | Operation::UMul(BinomialOperationData(a, b))
+            | Operation::UDiv(BinomialOperationData(a, b)) => (a.into(), Some(b.into())),
+            Operation::WSum(BinomialOperationData(a, b))
+            | Operation::WSub(BinomialOperationData(a, b))
+            | Operation::WMul(BinomialOperationData(a, b))
+            | Operation::WDiv(BinomialOperationData(a, b)) => (a.into(), Some(b.into())),
+            Operation::Fact(MonomialOperationData(a))
+            | Operation::Isqrt(MonomialOperationData(a))
+            | Operation::Ilog2(MonomialOperationData(a))
+            | Operation::PopCount(MonomialOperationData(a)) => (a.into(), None),
+        }
+    }
+
+    /// The symbol or function name of the operation, as used by [`Display`]
+    pub fn operator(&self) -> &'static str {
+        match self {
+            Operation::Sum(_) | Operation::USum(_) | Operation::WSum(_) => "+",
+            Operation::Sub(_) | Operation::USub(_) | Operation::WSub(_) => "-",
+            Operation::Mul(_) | Operation::UMul(_) | Operation::WMul(_) => "×",
+            Operation::Div(_) | Operation::UDiv(_) | Operation::WDiv(_) => "÷",
+            Operation::Rem(_) => "%",
+            Operation::Fact(_) => "!",
+            Operation::Min(_) => "min",
+            Operation::Max(_) => "max",
+            Operation::Mod(_) => "mod",
+            Operation::And(_) => "&",
+            Operation::Or(_) => "|",
+            Operation::Xor(_) => "^^",
+            Operation::Shl(_) => "<<",
+            Operation::Shr(_) => ">>",
+            Operation::Isqrt(_) => "sqrt",
+            Operation::PopCount(_) => "popcount",
+            Operation::Ilog2(_) => "log2",
+            Operation::ModPow(_) => "modpow",
+            Operation::AbsDiff(_) => "absdiff",
+            Operation::Avg(_) => "avg",
+        }
+    }
+
+    pub fn is_commutative(&self) -> bool {
+        matches!(
+            self,
+            Operation::Sum(_)
+                | Operation::Mul(_)
+                | Operation::Min(_)
+                | Operation::Max(_)
+                | Operation::And(_)
+                | Operation::Or(_)
+                | Operation::Xor(_)
+                | Operation::AbsDiff(_)
+                | Operation::Avg(_)
+                | Operation::USum(_)
+                | Operation::UMul(_)
+                | Operation::WSum(_)
+                | Operation::WMul(_)
+        )
+    }
+
+    /// Rough estimate of the work needed to reduce the operation: the number
+    /// of iterations of its loop, or 1 if it has none
+    pub fn cost(&self) -> u32 {
+        match self {
+            Operation::Fact(MonomialOperationData(a)) => (*a).max(0) as u32,
+            Operation::ModPow(TrinomialOperationData(_, exp, _)) => {
+                (i8::BITS - exp.max(&0).leading_zeros()).max(1)
+            }
+            _ => 1,
+        }
+    }
+
+    /// The operation undoing the effect of this one on the accumulator, that
+    /// is, subtracting its result. Factorials and divisions have none, as
+    /// neither do the operations that cannot be computed or whose result does
+    /// not fit in 16 bits.
+    pub fn inverse(&self) -> Option<Operation> {
+        if matches!(self, Operation::Fact(_) | Operation::Div(_)) {
+            return None;
+        }
+        let result = self.reduce().ok()?;
+        match (i8::try_from(result), i16::try_from(result)) {
+            (Ok(result), _) => Some(Operation::Sub((0, result).into())),
+            (_, Ok(result)) if result != i16::MIN => Some(Operation::WSub((0, result).into())),
+            _ => None,
+        }
+    }
+
+    /// Canonical form of the operation: commutative operations get their
+    /// smallest operand first, so that `3+4` and `4+3` are equal.
+    pub fn normalize(&self) -> Operation {
+        let mut operation = self.clone();
+        match &mut operation {
+            Operation::Sum(BinomialOperationData(a, b))
+            | Operation::Mul(BinomialOperationData(a, b))
+            | Operation::Min(BinomialOperationData(a, b))
+            | Operation::Max(BinomialOperationData(a, b))
+            | Operation::And(BinomialOperationData(a, b))
+            | Operation::Or(BinomialOperationData(a, b))
+            | Operation::Xor(BinomialOperationData(a, b))
+            | Operation::AbsDiff(BinomialOperationData(a, b))
+            | Operation::Avg(BinomialOperationData(a, b))
+                if *a > *b =>
+            {
+                std::mem::swap(a, b)
+            }
+            Operation::USum(BinomialOperationData(a, b))
+            | Operation::UMul(BinomialOperationData(a, b))
+                if *a > *b =>
+            {
+                std::mem::swap(a, b)
+            }
+            Operation::WSum(BinomialOperationData(a, b))
+            | Operation::WMul(BinomialOperationData(a, b))
+                if *a > *b =>
+            {
+                std::mem::swap(a, b)
+            }
+            _ => {}
+        }
+        operation
+    }
+
     pub fn reduce(&self) -> Result<i64, OperationError> {
+        self.reduce_with(ArithMode::Checked)
+    }
+
+    /// Adds the results of `ops` to `start`, saturating like the server
+    /// does. Returns the final accumulator and the result of each operation.
+    pub fn reduce_all(ops: &[Operation], start: i64) -> (i64, Vec<Result<i64, OperationError>>) {
+        let results: Vec<_> = ops.iter().map(Operation::reduce).collect();
+        let acc = results
+            .iter()
+            .flatten()
+            .fold(start, |acc, res| acc.saturating_add(*res));
+        (acc, results)
+    }
+
+    /// A short sequence of operations whose results add up to `target`:
+    /// a shift of every non-zero base 128 digit of its magnitude, with the
+    /// sign of `target`, to its place. There are at most ten of them.
+    pub fn decompose_to(target: i64) -> Vec<Operation> {
+        let sign = target.signum() as i8;
+        let magnitude = target.unsigned_abs();
+
+        (0..64)
+            .step_by(7)
+            .map(|shift| ((magnitude >> shift) & 0x7f, shift))
+            .filter(|&(digit, _)| digit != 0)
+            .map(|(digit, shift)| Operation::Shl((digit as i8 * sign, shift).into()))
+            .collect()
+    }
+
+    /// Computes the operation using `mode` for the `i16` arithmetic of
+    /// sums, subtractions and products.
+    pub fn reduce_with(&self, mode: ArithMode) -> Result<i64, OperationError> {
         Ok(match *self {
-            Operation::Sum(BinomialOperationData(a, b)) => (a as i16)
-                .checked_add(b as i16)
-                .ok_or(OperationError::OverFlow)?
-                .into(),
-            Operation::Sub(BinomialOperationData(a, b)) => (a as i16)
-                .checked_sub(b as i16)
-                .ok_or(OperationError::OverFlow)?
-                .into(),
-            Operation::Mul(BinomialOperationData(a, b)) => (a as i16)
-                .checked_mul(b as i16)
-                .ok_or(OperationError::OverFlow)?
-                .into(),
+            Operation::Sum(BinomialOperationData(a, b)) => mode.apply(
+                (a.into(), b.into()),
+                i16::checked_add,
+                i16::saturating_add,
+                i16::wrapping_add,
+            )?,
+            Operation::Sub(BinomialOperationData(a, b)) => mode.apply(
+                (a.into(), b.into()),
+                i16::checked_sub,
+                i16::saturating_sub,
+                i16::wrapping_sub,
+            )?,
+            Operation::Mul(BinomialOperationData(a, b)) => mode.apply(
+                (a.into(), b.into()),
+                i16::checked_mul,
+                i16::saturating_mul,
+                i16::wrapping_mul,
+            )?,
+            Operation::Div(BinomialOperationData(_, 0))
+            | Operation::Rem(BinomialOperationData(_, 0))
+            | Operation::Mod(BinomialOperationData(_, 0)) => {
+                return Err(OperationError::WrongDomain)
+            }
             Operation::Div(BinomialOperationData(a, b)) => {
-                a.checked_div(b).ok_or(OperationError::WrongDomain)?.into()
+                a.checked_div(b).ok_or(OperationError::DivOverflow)?.into()
             }
             Operation::Rem(BinomialOperationData(a, b)) => {
-                a.checked_rem(b).ok_or(OperationError::WrongDomain)?.into()
+                a.checked_rem(b).ok_or(OperationError::DivOverflow)?.into()
+            }
+            Operation::Mod(BinomialOperationData(a, b)) => i64::from(a).rem_euclid(b.into()),
+            Operation::Fact(MonomialOperationData(0)) => 1,
+            Operation::Fact(MonomialOperationData(a)) if a > Self::MAX_FACTORIAL_INPUT => {
+                return Err(OperationError::OverFlow)
+            }
+            Operation::Fact(MonomialOperationData(a)) if a > 0 => (1..=a.into())
+                .try_fold(1i64, |acc, e| acc.checked_mul(e))
+                .ok_or(OperationError::OverFlow)?,
+            Operation::Min(BinomialOperationData(a, b)) => a.min(b).into(),
+            Operation::Max(BinomialOperationData(a, b)) => a.max(b).into(),
+            Operation::And(BinomialOperationData(a, b)) => (a & b).into(),
+            Operation::Or(BinomialOperationData(a, b)) => (a | b).into(),
+            Operation::Xor(BinomialOperationData(a, b)) => (a ^ b).into(),
+            Operation::Shl(BinomialOperationData(_, b))
+            | Operation::Shr(BinomialOperationData(_, b))
+                if !(0..64).contains(&b) =>
+            {
+                return Err(OperationError::WrongDomain)
+            }
+            Operation::Shl(BinomialOperationData(a, b)) => i64::from(a) << b,
+            Operation::Shr(BinomialOperationData(a, b)) => i64::from(a) >> b,
+            Operation::Isqrt(MonomialOperationData(a)) if a < 0 => {
+                return Err(OperationError::WrongDomain)
+            }
+            Operation::Isqrt(MonomialOperationData(a)) => a.isqrt().into(),
+            Operation::Ilog2(MonomialOperationData(a)) => {
+                a.checked_ilog2().ok_or(OperationError::WrongDomain)?.into()
             }
-            Operation::Fact(MonomialOperationData(a)) if a == 0 => 1,
-            Operation::Fact(MonomialOperationData(a)) if a > 0 => {
-                (1..=a.into()).fold(Ok(1i64), |acc, e| match acc {
-                    Ok(n) => n.checked_mul(e).ok_or(OperationError::OverFlow),
-                    e => e,
-                })?
+            Operation::PopCount(MonomialOperationData(a)) => a.count_ones().into(),
+            Operation::ModPow(TrinomialOperationData(a, b, c)) => {
+                modpow(a.into(), b.into(), c.into())?
             }
+            Operation::AbsDiff(BinomialOperationData(a, b)) => a.abs_diff(b).into(),
+            // Integer division truncates toward zero, so avg(-3,-4) is -3
+            Operation::Avg(BinomialOperationData(a, b)) => {
+                ((i16::from(a) + i16::from(b)) / 2).into()
+            }
+            Operation::USum(BinomialOperationData(a, b)) => i64::from(a) + i64::from(b),
+            Operation::USub(BinomialOperationData(a, b)) => i64::from(a) - i64::from(b),
+            Operation::UMul(BinomialOperationData(a, b)) => i64::from(a) * i64::from(b),
+            Operation::UDiv(BinomialOperationData(_, 0)) => {
+                return Err(OperationError::WrongDomain)
+            }
+            Operation::UDiv(BinomialOperationData(a, b)) => (a / b).into(),
+            // Like a 16-bit ALU, without widening the operands first
+            Operation::WSum(BinomialOperationData(a, b)) => mode.apply(
+                (a, b),
+                i16::checked_add,
+                i16::saturating_add,
+                i16::wrapping_add,
+            )?,
+            Operation::WSub(BinomialOperationData(a, b)) => mode.apply(
+                (a, b),
+                i16::checked_sub,
+                i16::saturating_sub,
+                i16::wrapping_sub,
+            )?,
+            Operation::WMul(BinomialOperationData(a, b)) => mode.apply(
+                (a, b),
+                i16::checked_mul,
+                i16::saturating_mul,
+                i16::wrapping_mul,
+            )?,
+            Operation::WDiv(BinomialOperationData(_, 0)) => {
+                return Err(OperationError::WrongDomain)
+            }
+            Operation::WDiv(BinomialOperationData(a, b)) => mode.apply(
+                (a, b),
+                i16::checked_div,
+                i16::saturating_div,
+                i16::wrapping_div,
+            )?,
             _ => return Err(OperationError::WrongDomain),
         })
     }
-    pub fn encode(self) -> Box<[u8]> {
-        match self {
-            Operation::Sum(data) => Tlv::new(TlvType::Sum, &data.encode()).unwrap().encode(),
-            Operation::Sub(data) => Tlv::new(TlvType::Sub, &data.encode()).unwrap().encode(),
-            Operation::Mul(data) => Tlv::new(TlvType::Mul, &data.encode()).unwrap().encode(),
-            Operation::Div(data) => Tlv::new(TlvType::Div, &data.encode()).unwrap().encode(),
-            Operation::Rem(data) => Tlv::new(TlvType::Rem, &data.encode()).unwrap().encode(),
-            Operation::Fact(data) => Tlv::new(TlvType::Fact, &data.encode()).unwrap().encode(),
+
+    /// Computes the operation without any range limit, returning the result
+    /// as a decimal string. Only factorials can exceed the `i64` range, and
+    /// they are multiplied on base 10⁹ limbs, which print directly in
+    /// decimal. The rest of the operations are delegated to
+    /// [`Operation::reduce`].
+    pub fn reduce_decimal(&self) -> Result<String, OperationError> {
+        match *self {
+            Operation::Fact(MonomialOperationData(a)) if a >= 0 => {
+                // Little endian limbs in base 10⁹
+                const BASE: u64 = 1_000_000_000;
+                let mut limbs = vec![1u64];
+                for n in 2..=a as u64 {
+                    let mut carry = 0;
+                    for limb in limbs.iter_mut() {
+                        let v = *limb * n + carry;
+                        *limb = v % BASE;
+                        carry = v / BASE;
+                    }
+                    if carry > 0 {
+                        limbs.push(carry);
+                    }
+                }
+
+                let mut limbs = limbs.iter().rev();
+                let mut res = limbs.next().map_or(String::new(), |l| l.to_string());
+                limbs.for_each(|l| res.push_str(&format!("{:09}", l)));
+                Ok(res)
+            }
+            _ => self.reduce().map(|res| res.to_string()),
         }
     }
-}
 
-impl<'a> TryFrom<Tlv<'a>> for Operation {
-    type Error = OperationError;
+    /// The TLV tag of the operation
+    pub fn tag(&self) -> TlvType {
+        match self {
+            Operation::Sum(_) => TlvType::Sum,
+            Operation::Sub(_) => TlvType::Sub,
+            Operation::Mul(_) => TlvType::Mul,
+            Operation::Div(_) => TlvType::Div,
+            Operation::Rem(_) => TlvType::Rem,
+            Operation::Mod(_) => TlvType::Mod,
+            Operation::Fact(_) => TlvType::Fact,
+            Operation::Min(_) => TlvType::Min,
+            Operation::Max(_) => TlvType::Max,
+            Operation::And(_) => TlvType::And,
+            Operation::Or(_) => TlvType::Or,
+            Operation::Xor(_) => TlvType::Xor,
+            Operation::Shl(_) => TlvType::Shl,
+            Operation::Shr(_) => TlvType::Shr,
+            Operation::Isqrt(_) => TlvType::Isqrt,
+            Operation::Ilog2(_) => TlvType::Ilog2,
+            Operation::ModPow(_) => TlvType::ModPow,
+            Operation::AbsDiff(_) => TlvType::AbsDiff,
+            Operation::Avg(_) => TlvType::Avg,
+            Operation::PopCount(_) => TlvType::PopCount,
+            Operation::USum(_) => TlvType::USum,
+            Operation::USub(_) => TlvType::USub,
+            Operation::UMul(_) => TlvType::UMul,
+            Operation::UDiv(_) => TlvType::UDiv,
+            Operation::WSum(_) => TlvType::WSum,
+            Operation::WSub(_) => TlvType::WSub,
+            Operation::WMul(_) => TlvType::WMul,
+            Operation::WDiv(_) => TlvType::WDiv,
+        }
+    }
 
-    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
-        Ok(match tlv.tag {
-            TlvType::Sum if tlv.length == 2 => {
-                Operation::Sum(<[u8; 2]>::try_from(tlv.data)?.into())
+    /// Length of the encoded TLV of the operation, header included
+    pub fn encoded_len(&self) -> usize {
+        let data_len = match self {
+            Operation::Fact(_)
+            | Operation::Isqrt(_)
+            | Operation::Ilog2(_)
+            | Operation::PopCount(_) => Self::monomial_len(),
+            Operation::ModPow(_) => Self::trinomial_len(),
+            Operation::WSum(_) | Operation::WSub(_) | Operation::WMul(_) | Operation::WDiv(_) => {
+                Self::wide_len()
             }
-            TlvType::Sub if tlv.length == 2 => {
-                Operation::Sub(<[u8; 2]>::try_from(tlv.data)?.into())
+            _ => Self::binomial_len(),
+        };
+        2 + data_len
+    }
+
+    /// The TLV representation of the operation, with its data stored in `buf`
+    pub fn to_tlv<'a>(&self, buf: &'a mut [u8; Self::MAX_DATA_LEN]) -> Tlv<'a> {
+        let len = match self {
+            Operation::Sum(data)
+            | Operation::Sub(data)
+            | Operation::Mul(data)
+            | Operation::Div(data)
+            | Operation::Rem(data)
+            | Operation::Mod(data)
+            | Operation::Min(data)
+            | Operation::Max(data)
+            | Operation::And(data)
+            | Operation::Or(data)
+            | Operation::Xor(data)
+            | Operation::Shl(data)
+            | Operation::Shr(data)
+            | Operation::AbsDiff(data)
+            | Operation::Avg(data) => {
+                buf[..Self::binomial_len()].copy_from_slice(&data.encode());
+                Self::binomial_len()
             }
-            TlvType::Mul if tlv.length == 2 => {
-                Operation::Mul(<[u8; 2]>::try_from(tlv.data)?.into())
+            Operation::USum(data)
+            | Operation::USub(data)
+            | Operation::UMul(data)
+            | Operation::UDiv(data) => {
+                buf[..Self::binomial_len()].copy_from_slice(&data.encode());
+                Self::binomial_len()
             }
-            TlvType::Div if tlv.length == 2 => {
-                Operation::Div(<[u8; 2]>::try_from(tlv.data)?.into())
+            Operation::Fact(data)
+            | Operation::Isqrt(data)
+            | Operation::Ilog2(data)
+            | Operation::PopCount(data) => {
+                buf[..Self::monomial_len()].copy_from_slice(&data.encode());
+                Self::monomial_len()
             }
-            TlvType::Rem if tlv.length == 2 => {
-                Operation::Rem(<[u8; 2]>::try_from(tlv.data)?.into())
+            Operation::ModPow(data) => {
+                buf[..Self::trinomial_len()].copy_from_slice(&data.encode());
+                Self::trinomial_len()
             }
-            TlvType::Fact if tlv.length == 1 => {
-                Operation::Fact(<[u8; 1]>::try_from(tlv.data)?.into())
+            Operation::WSum(data)
+            | Operation::WSub(data)
+            | Operation::WMul(data)
+            | Operation::WDiv(data) => {
+                buf[..Self::wide_len()].copy_from_slice(&data.encode());
+                Self::wide_len()
             }
+        };
+
+        let buf: &'a [u8] = buf;
+        Tlv::new(self.tag(), &buf[..len]).unwrap()
+    }
+
+    /// Writes the encoded operation to `w` without allocating
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut buf = [0u8; Self::MAX_DATA_LEN];
+        self.to_tlv(&mut buf).write_to(w)
+    }
+
+    pub fn encode(self) -> Box<[u8]> {
+        let mut buf = [0u8; Self::MAX_DATA_LEN];
+        self.to_tlv(&mut buf).encode()
+    }
+}
+
+impl<'a> TryFrom<Tlv<'a>> for Operation {
+    type Error = OperationError;
+
+    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
+        const BINOMIAL: usize = Operation::binomial_len();
+        const MONOMIAL: usize = Operation::monomial_len();
+        const TRINOMIAL: usize = Operation::trinomial_len();
+        const WIDE: usize = Operation::wide_len();
+
+        Ok(match tlv.tag {
+            TlvType::Sum => Operation::Sum(tlv.fixed::<BINOMIAL>()?.into()),
+            TlvType::Sub => Operation::Sub(tlv.fixed::<BINOMIAL>()?.into()),
+            TlvType::Mul => Operation::Mul(tlv.fixed::<BINOMIAL>()?.into()),
+            TlvType::Div => Operation::Div(tlv.fixed::<BINOMIAL>()?.into()),
+            TlvType::Rem => Operation::Rem(tlv.fixed::<BINOMIAL>()?.into()),
+            TlvType::Mod => Operation::Mod(tlv.fixed::<BINOMIAL>()?.into()),
+            TlvType::Fact => Operation::Fact(tlv.fixed::<MONOMIAL>()?.into()),
+            TlvType::Min => Operation::Min(tlv.fixed::<BINOMIAL>()?.into()),
+            TlvType::Max => Operation::Max(tlv.fixed::<BINOMIAL>()?.into()),
+            TlvType::And => Operation::And(tlv.fixed::<BINOMIAL>()?.into()),
+            TlvType::Or => Operation::Or(tlv.fixed::<BINOMIAL>()?.into()),
+            TlvType::Xor => Operation::Xor(tlv.fixed::<BINOMIAL>()?.into()),
+            TlvType::Shl => Operation::Shl(tlv.fixed::<BINOMIAL>()?.into()),
+            TlvType::Shr => Operation::Shr(tlv.fixed::<BINOMIAL>()?.into()),
+            TlvType::Isqrt => Operation::Isqrt(tlv.fixed::<MONOMIAL>()?.into()),
+            TlvType::Ilog2 => Operation::Ilog2(tlv.fixed::<MONOMIAL>()?.into()),
+            TlvType::ModPow => Operation::ModPow(tlv.fixed::<TRINOMIAL>()?.into()),
+            TlvType::AbsDiff => Operation::AbsDiff(tlv.fixed::<BINOMIAL>()?.into()),
+            TlvType::Avg => Operation::Avg(tlv.fixed::<BINOMIAL>()?.into()),
+            TlvType::PopCount => Operation::PopCount(tlv.fixed::<MONOMIAL>()?.into()),
+            TlvType::USum => Operation::USum(tlv.fixed::<BINOMIAL>()?.into()),
+            TlvType::USub => Operation::USub(tlv.fixed::<BINOMIAL>()?.into()),
+            TlvType::UMul => Operation::UMul(tlv.fixed::<BINOMIAL>()?.into()),
+            TlvType::UDiv => Operation::UDiv(tlv.fixed::<BINOMIAL>()?.into()),
+            TlvType::WSum => Operation::WSum(tlv.fixed::<WIDE>()?.into()),
+            TlvType::WSub => Operation::WSub(tlv.fixed::<WIDE>()?.into()),
+            TlvType::WMul => Operation::WMul(tlv.fixed::<WIDE>()?.into()),
+            TlvType::WDiv => Operation::WDiv(tlv.fixed::<WIDE>()?.into()),
             _ => return Err(OperationError::Generic),
         })
     }
 }
 
+impl<'a> TryFrom<&'a [u8]> for Operation {
+    type Error = OperationError;
+
+    fn try_from(bytes: &'a [u8]) -> Result<Self, Self::Error> {
+        Tlv::try_from(bytes)?.try_into()
+    }
+}
+
 impl Display for Operation {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -194,7 +822,142 @@ impl Display for Operation {
             Operation::Div(BinomialOperationData(a, b)) => write!(f, "{}÷{}", a, b),
             Operation::Rem(BinomialOperationData(a, b)) => write!(f, "{}%{}", a, b),
             Operation::Fact(MonomialOperationData(a)) => write!(f, "{}!", a),
+            Operation::Min(BinomialOperationData(a, b)) => write!(f, "min({},{})", a, b),
+            Operation::Max(BinomialOperationData(a, b)) => write!(f, "max({},{})", a, b),
+            Operation::Mod(BinomialOperationData(a, b)) => write!(f, "{} mod {}", a, b),
+            Operation::And(BinomialOperationData(a, b)) => write!(f, "{}&{}", a, b),
+            Operation::Or(BinomialOperationData(a, b)) => write!(f, "{}|{}", a, b),
+            Operation::Xor(BinomialOperationData(a, b)) => write!(f, "{}^^{}", a, b),
+            Operation::Shl(BinomialOperationData(a, b)) => write!(f, "{}<<{}", a, b),
+            Operation::Shr(BinomialOperationData(a, b)) => write!(f, "{}>>{}", a, b),
+            Operation::Isqrt(MonomialOperationData(a)) => write!(f, "sqrt({})", a),
+            Operation::Ilog2(MonomialOperationData(a)) => write!(f, "log2({})", a),
+            Operation::ModPow(TrinomialOperationData(a, b, c)) => {
+                write!(f, "modpow({},{},{})", a, b, c)
+            }
+            Operation::AbsDiff(BinomialOperationData(a, b)) => write!(f, "absdiff({},{})", a, b),
+            Operation::Avg(BinomialOperationData(a, b)) => write!(f, "avg({},{})", a, b),
+            Operation::PopCount(MonomialOperationData(a)) => write!(f, "popcount({})", a),
+            Operation::USum(BinomialOperationData(a, b)) => write!(f, "{}u+{}u", a, b),
+            Operation::USub(BinomialOperationData(a, b)) => write!(f, "{}u-{}u", a, b),
+            Operation::UMul(BinomialOperationData(a, b)) => write!(f, "{}u×{}u", a, b),
+            Operation::UDiv(BinomialOperationData(a, b)) => write!(f, "{}u÷{}u", a, b),
+            Operation::WSum(BinomialOperationData(a, b)) => write!(f, "{}+{}", a, b),
+            Operation::WSub(BinomialOperationData(a, b)) => write!(f, "{}-{}", a, b),
+            Operation::WMul(BinomialOperationData(a, b)) => write!(f, "{}×{}", a, b),
+            Operation::WDiv(BinomialOperationData(a, b)) => write!(f, "{}÷{}", a, b),
+        }
+    }
+}
+
+/// Parses an operand, telling apart the numbers that do not fit
+/// in it from the text that is not a number at all
+fn parse_operand<T: TryFrom<i64>>(s: &str) -> Result<T, OperationError> {
+    let value: i64 = s.parse()?;
+    T::try_from(value).map_err(|_| OperationError::OperandOutOfRange { value })
+}
+
+/// A piece of an operation written as text
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    /// A number, flagged if it has the `u` suffix of unsigned numbers. It may
+    /// have a decimal part, which integer operations reject.
+    Number(&'a str, bool),
+    /// A function name or an operator written as a word, in lowercase
+    Word(String),
+    Symbol(&'static str),
+    Open,
+    Close,
+    Comma,
+}
+
+/// Splits `s` in tokens. A `-` is the sign of a number unless it follows
+/// another number.
+fn tokenize(s: &str) -> Result<Vec<Token<'_>>, OperationError> {
+    const SYMBOLS: [&str; 13] = [
+        "^^", "<<", ">>", "+", "-", "*", "×", "/", "÷", "%", "!", "&", "|",
+    ];
+
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let after_number = matches!(tokens.last(), Some(Token::Number(..)));
+        let len = match c {
+            // A sign must be right before the digits and cannot follow a number,
+            // so that `5 - -3` is a subtraction and `5 ++ 3` is rejected
+            '0'..='9' | '-' | '+'
+                if c.is_ascii_digit()
+                    || !after_number && rest[1..].starts_with(|c: char| c.is_ascii_digit()) =>
+            {
+                let mut digits = rest[1..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .map_or(rest.len(), |i| i + 1);
+                if rest[digits..].starts_with('.')
+                    && rest[digits + 1..].starts_with(|c: char| c.is_ascii_digit())
+                {
+                    digits = rest[digits + 1..]
+                        .find(|c: char| !c.is_ascii_digit())
+                        .map_or(rest.len(), |i| i + digits + 1);
+                }
+                let unsigned = rest[digits..].starts_with('u')
+                    && !rest[digits + 1..].starts_with(char::is_alphanumeric);
+                tokens.push(Token::Number(&rest[..digits], unsigned));
+                digits + usize::from(unsigned)
+            }
+            c if c.is_alphabetic() => {
+                let letters = rest
+                    .find(|c: char| !c.is_alphabetic())
+                    .unwrap_or(rest.len());
+                let word = rest
+                    .find(|c: char| !c.is_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len());
+                // Function names may have digits, but operators are followed by numbers
+                let len = match rest[word..].trim_start().starts_with('(') {
+                    true => word,
+                    false => letters,
+                };
+                tokens.push(Token::Word(rest[..len].to_lowercase()));
+                len
+            }
+            '(' => {
+                tokens.push(Token::Open);
+                1
+            }
+            ')' => {
+                tokens.push(Token::Close);
+                1
+            }
+            ',' => {
+                tokens.push(Token::Comma);
+                1
+            }
+            _ => {
+                let symbol = SYMBOLS
+                    .into_iter()
+                    .find(|symbol| rest.starts_with(symbol))
+                    .ok_or(OperationError::Parse)?;
+                tokens.push(Token::Symbol(symbol));
+                symbol.len()
+            }
+        };
+        rest = rest[len..].trim_start();
+    }
+
+    Ok(tokens)
+}
+
+/// Splits the tokens of `a op b`, with signed operands, in its three parts
+fn signed_binomial<'t>(tokens: &'t [Token]) -> Result<(&'t str, &'t str, &'t str), OperationError> {
+    match tokens {
+        [Token::Number(a, false), operator, Token::Number(b, false)] => {
+            let operator = match operator {
+                Token::Symbol(symbol) => symbol,
+                Token::Word(word) => word.as_str(),
+                _ => return Err(OperationError::Parse),
+            };
+            Ok((a, operator, b))
         }
+        _ => Err(OperationError::Parse),
     }
 }
 
@@ -202,42 +965,268 @@ impl FromStr for Operation {
     type Err = OperationError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let regex = Regex::new(r"^\s*(\-?\d+)\s*([+\-*×x/÷%!])\s*(\-?\d+)?\s*$").unwrap();
-        let elements: Box<_> = match regex.captures(s) {
-            Some(captures) => captures
-                .iter()
-                .skip(1)
-                .map(|c| c.map(|m| m.as_str()))
-                .collect(),
-            None => return Err(OperationError::Parse),
+        let tokens = tokenize(s)?;
+        let (a, operator, b) = match &tokens[..] {
+            [Token::Word(name), Token::Open, args @ .., Token::Close] => {
+                // The arguments are numbers separated by commas, with none
+                // after the last one
+                if let Some(Token::Comma) = args.last() {
+                    return Err(OperationError::Parse);
+                }
+                let args = args
+                    .iter()
+                    .enumerate()
+                    .map(|(i, token)| match (i & 1, token) {
+                        (0, Token::Number(arg, false)) => parse_operand(arg).map(Some),
+                        (1, Token::Comma) => Ok(None),
+                        _ => Err(OperationError::Parse),
+                    })
+                    .filter_map(Result::transpose)
+                    .collect::<Result<Box<[i8]>, _>>()?;
+                return Operation::from_function(name, &args);
+            }
+            [Token::Number(a, false), Token::Symbol("!")] => {
+                return Ok(Operation::Fact(parse_operand::<i8>(a)?.into()))
+            }
+            [Token::Number(a, ua), operator, Token::Number(b, ub)] => {
+                let operator = match operator {
+                    Token::Symbol(symbol) => *symbol,
+                    Token::Word(word) if TlvType::from_operator(word).is_ok() => word.as_str(),
+                    _ => return Err(OperationError::Parse),
+                };
+                ((*a, *ua), operator, (*b, *ub))
+            }
+            _ => return Err(OperationError::Parse),
         };
 
-        let (a, b) = match elements[..] {
-            [Some(match_a), Some(_), Some(match_b)] => (match_a.parse()?, match_b.parse()?),
-            [Some(match_a), Some(_), None] => (match_a.parse()?, 0i8),
-            _ => {
-                return Err(OperationError::Parse);
+        let unsupported = || OperationError::UnsupportedOperation(operator.into());
+        let tag = TlvType::from_operator(operator).map_err(|_| unsupported())?;
+
+        // Operands with a `u` suffix are unsigned, both must have it
+        let operation = match (a, b) {
+            ((a, true), (b, true)) => {
+                let (a, b): (u8, u8) = (parse_operand(a)?, parse_operand(b)?);
+                match tag {
+                    TlvType::Sum => Operation::USum((a, b).into()),
+                    TlvType::Sub => Operation::USub((a, b).into()),
+                    TlvType::Mul => Operation::UMul((a, b).into()),
+                    TlvType::Div => Operation::UDiv((a, b).into()),
+                    _ => return Err(unsupported()),
+                }
+            }
+            ((a, false), (b, false)) => {
+                let (a, b): (i8, i8) = (parse_operand(a)?, parse_operand(b)?);
+                match tag {
+                    TlvType::Sum => Operation::Sum((a, b).into()),
+                    TlvType::Sub => Operation::Sub((a, b).into()),
+                    TlvType::Mul => Operation::Mul((a, b).into()),
+                    TlvType::Div => Operation::Div((a, b).into()),
+                    TlvType::Rem => Operation::Rem((a, b).into()),
+                    TlvType::Mod => Operation::Mod((a, b).into()),
+                    TlvType::And => Operation::And((a, b).into()),
+                    TlvType::Or => Operation::Or((a, b).into()),
+                    TlvType::Xor => Operation::Xor((a, b).into()),
+                    TlvType::Shl => Operation::Shl((a, b).into()),
+                    TlvType::Shr => Operation::Shr((a, b).into()),
+                    _ => return Err(unsupported()),
+                }
             }
+            _ => return Err(OperationError::Parse),
         };
 
-        let operation = match elements[1] {
-            Some("+") if elements[2].is_some() => Operation::Sum((a, b).into()),
-            Some("-") if elements[2].is_some() => Operation::Sub((a, b).into()),
-            Some("*" | "×" | "x") if elements[2].is_some() => Operation::Mul((a, b).into()),
-            Some("/" | "÷") if elements[2].is_some() => Operation::Div((a, b).into()),
-            Some("%") if elements[2].is_some() => Operation::Rem((a, b).into()),
-            Some("!") if elements[2].is_none() => Operation::Fact(a.into()),
-            Some(op) => return Err(OperationError::UnsupportedOperation(op.into())),
-            None => return Err(OperationError::Parse),
+        Ok(operation)
+    }
+}
+
+impl Operation {
+    /// Parses an operation in Reverse Polish Notation, such as `3 4 +`,
+    /// `5 !` or `2 10 100 modpow`. The expression must consist of the
+    /// operands followed by a single operator.
+    pub fn from_rpn(s: &str) -> Result<Self, OperationError> {
+        let tokens: Box<[&str]> = s.split_whitespace().collect();
+        let (&operator, operands) = tokens.split_last().ok_or(OperationError::Parse)?;
+        let operands = operands
+            .iter()
+            .map(|operand| parse_operand(operand))
+            .collect::<Result<Box<[i8]>, _>>()?;
+        let operator = match operator.starts_with(char::is_alphabetic) {
+            true => operator.to_lowercase(),
+            false => operator.to_string(),
         };
+        let function =
+            operator.starts_with(char::is_alphabetic) && TlvType::from_operator(&operator).is_err();
 
-        Ok(operation)
+        match operands[..] {
+            [] => Err(OperationError::Parse),
+            _ if function => Operation::from_function(&operator, &operands),
+            [a] => format!("{a}{operator}").parse(),
+            [a, b] => format!("{a} {operator} {b}").parse(),
+            _ => Err(OperationError::Parse),
+        }
+    }
+
+    /// Parses a sum, subtraction, product or division with 16 bit operands
+    pub fn from_str_wide(s: &str) -> Result<Self, OperationError> {
+        let tokens = tokenize(s)?;
+        let (a, operator, b) = signed_binomial(&tokens)?;
+        let (a, b): (i16, i16) = (parse_operand(a)?, parse_operand(b)?);
+
+        Ok(match TlvType::from_operator(operator) {
+            Ok(TlvType::Sum) => Operation::WSum((a, b).into()),
+            Ok(TlvType::Sub) => Operation::WSub((a, b).into()),
+            Ok(TlvType::Mul) => Operation::WMul((a, b).into()),
+            Ok(TlvType::Div) => Operation::WDiv((a, b).into()),
+            _ => return Err(OperationError::UnsupportedOperation(operator.into())),
+        })
+    }
+
+    /// Builds the operation computed by the function `name`, in lowercase
+    fn from_function(name: &str, args: &[i8]) -> Result<Self, OperationError> {
+        Ok(match (name, args) {
+            ("min", &[a, b]) => Operation::Min((a, b).into()),
+            ("max", &[a, b]) => Operation::Max((a, b).into()),
+            ("sqrt", &[a]) => Operation::Isqrt(a.into()),
+            ("log2", &[a]) => Operation::Ilog2(a.into()),
+            ("modpow", &[a, b, c]) => Operation::ModPow((a, b, c).into()),
+            ("absdiff", &[a, b]) => Operation::AbsDiff((a, b).into()),
+            ("popcount", &[a]) => Operation::PopCount(a.into()),
+            ("avg", &[a, b]) => Operation::Avg((a, b).into()),
+            ("min" | "max" | "sqrt" | "log2" | "modpow" | "absdiff" | "popcount" | "avg", _) => {
+                return Err(OperationError::Parse)
+            }
+            (name, _) => return Err(OperationError::UnsupportedOperation(name.into())),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FloatOperation {
+    Sum(BinomialOperationData<f32, f32>),
+    Sub(BinomialOperationData<f32, f32>),
+    Mul(BinomialOperationData<f32, f32>),
+    Div(BinomialOperationData<f32, f32>),
+}
+
+impl FloatOperation {
+    pub fn reduce(&self) -> Result<f64, OperationError> {
+        Ok(match *self {
+            FloatOperation::Sum(BinomialOperationData(a, b)) => a as f64 + b as f64,
+            FloatOperation::Sub(BinomialOperationData(a, b)) => a as f64 - b as f64,
+            FloatOperation::Mul(BinomialOperationData(a, b)) => a as f64 * b as f64,
+            FloatOperation::Div(BinomialOperationData(_, 0.0)) => {
+                return Err(OperationError::WrongDomain)
+            }
+            FloatOperation::Div(BinomialOperationData(a, b)) => a as f64 / b as f64,
+        })
+    }
+
+    pub fn encode(self) -> Box<[u8]> {
+        let (tag, data) = match self {
+            FloatOperation::Sum(data) => (TlvType::SumF, data.encode()),
+            FloatOperation::Sub(data) => (TlvType::SubF, data.encode()),
+            FloatOperation::Mul(data) => (TlvType::MulF, data.encode()),
+            FloatOperation::Div(data) => (TlvType::DivF, data.encode()),
+        };
+
+        Tlv::new(tag, &data).unwrap().encode()
+    }
+}
+
+impl<'a> TryFrom<Tlv<'a>> for FloatOperation {
+    type Error = OperationError;
+
+    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
+        const BINOMIAL: usize = BinomialOperationData::<f32, f32>::WIRE_LEN;
+
+        Ok(match tlv.tag {
+            TlvType::SumF => FloatOperation::Sum(tlv.fixed::<BINOMIAL>()?.into()),
+            TlvType::SubF => FloatOperation::Sub(tlv.fixed::<BINOMIAL>()?.into()),
+            TlvType::MulF => FloatOperation::Mul(tlv.fixed::<BINOMIAL>()?.into()),
+            TlvType::DivF => FloatOperation::Div(tlv.fixed::<BINOMIAL>()?.into()),
+            _ => return Err(OperationError::Generic),
+        })
+    }
+}
+
+impl Display for FloatOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FloatOperation::Sum(BinomialOperationData(a, b)) => write!(f, "{}+{}", a, b),
+            FloatOperation::Sub(BinomialOperationData(a, b)) => write!(f, "{}-{}", a, b),
+            FloatOperation::Mul(BinomialOperationData(a, b)) => write!(f, "{}×{}", a, b),
+            FloatOperation::Div(BinomialOperationData(a, b)) => write!(f, "{}÷{}", a, b),
+        }
+    }
+}
+
+impl FromStr for FloatOperation {
+    type Err = OperationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let (a, operator, b) = signed_binomial(&tokens)?;
+        let (a, b): (f32, f32) = (a.parse()?, b.parse()?);
+
+        Ok(match TlvType::from_operator(operator) {
+            Ok(TlvType::Sum) => FloatOperation::Sum((a, b).into()),
+            Ok(TlvType::Sub) => FloatOperation::Sub((a, b).into()),
+            Ok(TlvType::Mul) => FloatOperation::Mul((a, b).into()),
+            Ok(TlvType::Div) => FloatOperation::Div((a, b).into()),
+            _ => return Err(OperationError::UnsupportedOperation(operator.into())),
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{Operation, Tlv};
+    use std::error::Error;
+
+    use crate::{
+        operation::{ArithMode, BinomialOperationData, FloatOperation, OperationError},
+        Operation, Tlv, TlvType,
+    };
+
+    #[test]
+    fn error_sources() {
+        let errors = [
+            OperationError::from(<[u8; 2]>::try_from(&[0u8][..]).unwrap_err()),
+            OperationError::from(u8::try_from(256).unwrap_err()),
+            OperationError::from("x".parse::<i8>().unwrap_err()),
+            OperationError::from("x".parse::<f32>().unwrap_err()),
+            OperationError::from(Tlv::try_from(&[1u8][..]).unwrap_err()),
+            Operation::try_from(&[1u8, 3, 0, 0][..]).unwrap_err(),
+            "99999999999999999999+1".parse::<Operation>().unwrap_err(),
+        ];
+        for error in errors {
+            assert!(error.source().is_some(), "{error:?} has no source");
+        }
+    }
+
+    #[test]
+    fn operand_out_of_range() {
+        for (s, value) in [("200 + 1", 200), ("1+-200", -200), ("300!", 300)] {
+            match s.parse::<Operation>() {
+                Err(OperationError::OperandOutOfRange { value: v }) => assert_eq!(v, value),
+                other => panic!("{s} parsed as {other:?}"),
+            }
+        }
+        assert_eq!(
+            "200+1".parse::<Operation>().unwrap_err().to_string(),
+            "200 doesn't fit in a single byte"
+        );
+        assert_eq!(
+            "-200+1".parse::<Operation>().unwrap_err().to_string(),
+            "-200 doesn't fit in a single byte"
+        );
+        assert!(matches!(
+            "256u+1u".parse::<Operation>(),
+            Err(OperationError::OperandOutOfRange { value: 256 })
+        ));
+        assert!(matches!(
+            "99999999999999999999+1".parse::<Operation>(),
+            Err(OperationError::ParseIntError(_))
+        ));
+    }
 
     #[test]
     fn parse_operation_sum() {
@@ -248,6 +1237,13 @@ mod tests {
         assert_eq!(operation.unwrap(), Operation::Sum((127, -1).into()));
     }
 
+    #[test]
+    fn parse_operation_from_bytes() {
+        let operation = Operation::try_from(&[1u8, 2, 127, 255][..]);
+        assert_eq!(operation.unwrap(), Operation::Sum((127, -1).into()));
+        assert!(Operation::try_from(&[1u8, 2, 127][..]).is_err());
+    }
+
     #[test]
     fn parse_operation_div_zero() {
         let tlv: Result<Tlv, _> = (&[4u8, 2, 100, 0][..]).try_into();
@@ -265,17 +1261,282 @@ mod tests {
     }
 
     #[test]
-    fn operation_fact_negative() {
-        assert!(Operation::Fact((-1).into()).reduce().is_err());
+    fn operation_div_zero() {
+        assert!(matches!(
+            Operation::Div((100, 0).into()).reduce(),
+            Err(OperationError::WrongDomain)
+        ));
+        assert!(matches!(
+            Operation::Rem((100, 0).into()).reduce(),
+            Err(OperationError::WrongDomain)
+        ));
     }
 
     #[test]
-    fn operation_fact_zero() {
-        let res = Operation::Fact((0).into()).reduce();
-        assert!(res.is_ok());
+    fn operation_div_overflow() {
+        assert!(matches!(
+            Operation::Div((i8::MIN, -1).into()).reduce(),
+            Err(OperationError::DivOverflow)
+        ));
+        assert!(matches!(
+            Operation::Rem((i8::MIN, -1).into()).reduce(),
+            Err(OperationError::DivOverflow)
+        ));
+        assert_eq!(Operation::Div((i8::MIN, 1).into()).reduce().unwrap(), -128);
+    }
+
+    #[test]
+    fn operation_arith_modes() {
+        // The i16 intermediate is wide enough for any pair of i8 operands
+        for mode in [
+            ArithMode::Checked,
+            ArithMode::Saturating,
+            ArithMode::Wrapping,
+        ] {
+            let sum = Operation::Sum((127, 127).into());
+            assert_eq!(sum.reduce_with(mode).unwrap(), 254);
+            let mul = Operation::Mul((-128, -128).into());
+            assert_eq!(mul.reduce_with(mode).unwrap(), 16384);
+        }
+    }
+
+    #[test]
+    fn arith_mode_apply() {
+        let sum = |mode: ArithMode| {
+            mode.apply(
+                (i16::MAX, 1),
+                i16::checked_add,
+                i16::saturating_add,
+                i16::wrapping_add,
+            )
+        };
+        assert!(matches!(
+            sum(ArithMode::Checked),
+            Err(OperationError::OverFlow)
+        ));
+        assert_eq!(sum(ArithMode::Saturating).unwrap(), i16::MAX.into());
+        assert_eq!(sum(ArithMode::Wrapping).unwrap(), i16::MIN.into());
+    }
+
+    #[test]
+    fn wide_saturation() {
+        // The i8 operations cannot overflow their i16 intermediate
+        let mul = Operation::Mul((100, 100).into());
+        assert_eq!(mul.reduce_with(ArithMode::Checked).unwrap(), 10000);
+        assert_eq!(mul.reduce_with(ArithMode::Saturating).unwrap(), 10000);
+
+        // But the i16 ones behave like a 16-bit ALU
+        let mul = Operation::WMul((1000, 100).into());
+        assert!(matches!(
+            mul.reduce_with(ArithMode::Checked),
+            Err(OperationError::OverFlow)
+        ));
+        assert_eq!(
+            mul.reduce_with(ArithMode::Saturating).unwrap(),
+            i16::MAX.into()
+        );
+        assert_eq!(
+            Operation::WMul((-1000, 100).into())
+                .reduce_with(ArithMode::Saturating)
+                .unwrap(),
+            i16::MIN.into()
+        );
+        assert_eq!(
+            mul.reduce_with(ArithMode::Wrapping).unwrap(),
+            (100000i64 as i16).into()
+        );
+
+        let sum = Operation::WSum((i16::MAX, 1).into());
+        assert!(sum.reduce().is_err());
+        assert_eq!(
+            sum.reduce_with(ArithMode::Saturating).unwrap(),
+            i16::MAX.into()
+        );
+        let div = Operation::WDiv((i16::MIN, -1).into());
+        assert!(div.reduce().is_err());
+        assert_eq!(
+            div.reduce_with(ArithMode::Saturating).unwrap(),
+            i16::MAX.into()
+        );
+    }
+
+    #[test]
+    fn operation_reduce_all() {
+        let ops = [
+            Operation::Sum((3, 4).into()),
+            Operation::Fact(21.into()),
+            Operation::Mul((-2, 5).into()),
+        ];
+        let (acc, results) = Operation::reduce_all(&ops, 100);
+        assert_eq!(acc, 97);
+        assert_eq!(results[0].as_ref().unwrap(), &7);
+        assert!(matches!(results[1], Err(OperationError::OverFlow)));
+        assert_eq!(results[2].as_ref().unwrap(), &-10);
+
+        let ops = [Operation::Fact(20.into()), Operation::Fact(20.into())];
+        let (acc, _) = Operation::reduce_all(&ops, i64::MAX - 1);
+        assert_eq!(acc, i64::MAX);
+        assert_eq!(Operation::reduce_all(&[], 5).0, 5);
+    }
+
+    #[test]
+    fn operation_fact_negative() {
+        assert!(Operation::Fact((-1).into()).reduce().is_err());
+    }
+
+    #[test]
+    fn operation_fact_zero() {
+        let res = Operation::Fact((0).into()).reduce();
+        assert!(res.is_ok());
         assert_eq!(res.unwrap(), 1);
     }
 
+    #[test]
+    fn operation_fact_max() {
+        let res = Operation::Fact(Operation::MAX_FACTORIAL_INPUT.into()).reduce();
+        assert_eq!(res.unwrap(), 2432902008176640000);
+    }
+
+    #[test]
+    fn operation_fact_overflow() {
+        assert!(matches!(
+            Operation::Fact((21).into()).reduce(),
+            Err(OperationError::OverFlow)
+        ));
+    }
+
+    #[test]
+    fn operation_fact_decimal() {
+        assert_eq!(
+            Operation::Fact((25).into()).reduce_decimal().unwrap(),
+            "15511210043330985984000000"
+        );
+        assert_eq!(Operation::Fact((0).into()).reduce_decimal().unwrap(), "1");
+        assert_eq!(Operation::Sum((1, 2).into()).reduce_decimal().unwrap(), "3");
+
+        // Products carried to a new limb, and inner limbs with leading zeros
+        for n in 0..=20 {
+            let operation = Operation::Fact(n.into());
+            assert_eq!(
+                operation.reduce_decimal().unwrap(),
+                operation.reduce().unwrap().to_string()
+            );
+        }
+        assert_eq!(
+            Operation::Fact((21).into()).reduce_decimal().unwrap(),
+            "51090942171709440000"
+        );
+
+        // Signs are kept, down to the smallest i64
+        assert_eq!(
+            Operation::Shl((-1, 63).into()).reduce_decimal().unwrap(),
+            i64::MIN.to_string()
+        );
+        assert_eq!(
+            Operation::Sub((-128, 127).into()).reduce_decimal().unwrap(),
+            "-255"
+        );
+        assert!(Operation::Fact((-3).into()).reduce_decimal().is_err());
+    }
+
+    #[test]
+    fn wire_lengths() {
+        assert_eq!(
+            Operation::Sum((1, 2).into()).encode().len(),
+            2 + Operation::binomial_len()
+        );
+        assert_eq!(
+            Operation::Fact(3.into()).encode().len(),
+            2 + Operation::monomial_len()
+        );
+        assert_eq!(
+            FloatOperation::Sum((1.0, 2.0).into()).encode().len(),
+            2 + BinomialOperationData::<f32, f32>::WIRE_LEN
+        );
+    }
+
+    #[test]
+    fn operation_introspection() {
+        let operation = Operation::Div((-10, 3).into());
+        assert_eq!(operation.operands(), (-10, Some(3)));
+        assert_eq!(operation.operator(), "÷");
+
+        let operation = Operation::Fact(5.into());
+        assert_eq!(operation.operands(), (5, None));
+        assert_eq!(operation.operator(), "!");
+    }
+
+    #[test]
+    fn operation_commutative() {
+        let commutative = [
+            Operation::Sum((4, 3).into()),
+            Operation::Mul((4, 3).into()),
+            Operation::Min((4, 3).into()),
+            Operation::Max((4, 3).into()),
+            Operation::And((4, 3).into()),
+            Operation::Or((4, 3).into()),
+            Operation::Xor((4, 3).into()),
+            Operation::AbsDiff((4, 3).into()),
+            Operation::Avg((4, 3).into()),
+        ];
+        for operation in commutative {
+            assert!(operation.is_commutative());
+            let normalized = operation.normalize();
+            assert_eq!(normalized.operands(), (3, Some(4)));
+            assert_eq!(normalized.reduce().unwrap(), operation.reduce().unwrap());
+            assert_eq!(normalized.normalize(), normalized);
+        }
+
+        let non_commutative = [
+            Operation::Sub((4, 3).into()),
+            Operation::Div((4, 3).into()),
+            Operation::Rem((4, 3).into()),
+            Operation::Shl((4, 3).into()),
+            Operation::Shr((4, 3).into()),
+            Operation::Fact(4.into()),
+            Operation::Isqrt(4.into()),
+            Operation::Ilog2(4.into()),
+            Operation::ModPow((4, 3, 5).into()),
+        ];
+        for operation in non_commutative {
+            assert!(!operation.is_commutative());
+            assert_eq!(operation.normalize(), operation);
+        }
+
+        assert_eq!(
+            Operation::Sum((3, 4).into()).normalize(),
+            Operation::Sum((4, 3).into()).normalize()
+        );
+    }
+
+    #[test]
+    fn operation_to_tlv() {
+        let mut buf = [0u8; Operation::MAX_DATA_LEN];
+
+        let tlv = Operation::Sum((10, -10).into()).to_tlv(&mut buf);
+        assert_eq!(tlv.tag, TlvType::Sum);
+        assert_eq!(tlv.data, [10u8, 246]);
+
+        let tlv = Operation::Fact(5.into()).to_tlv(&mut buf);
+        assert_eq!(tlv.tag, TlvType::Fact);
+        assert_eq!(tlv.length, 1);
+        assert_eq!(tlv.data, [5u8]);
+    }
+
+    #[test]
+    fn operation_write_to() {
+        for operation in [
+            Operation::Sum((10, -10).into()),
+            Operation::Fact(5.into()),
+            Operation::ModPow((2, 10, 100).into()),
+        ] {
+            let mut written = vec![];
+            operation.write_to(&mut written).unwrap();
+            operation.write_to(&mut written).unwrap();
+            assert_eq!(written[..], operation.clone().encode().repeat(2)[..]);
+        }
+    }
+
     #[test]
     fn encode_sub() {
         assert_eq!(
@@ -288,4 +1549,588 @@ mod tests {
     fn encode_fact() {
         assert_eq!(Operation::Fact((100).into()).encode()[..], [6u8, 1, 100]);
     }
+
+    #[test]
+    fn operation_min_max() {
+        assert_eq!(Operation::Min((3, 9).into()).reduce().unwrap(), 3);
+        assert_eq!(Operation::Max((3, 9).into()).reduce().unwrap(), 9);
+        assert_eq!(Operation::Min((-4, -4).into()).reduce().unwrap(), -4);
+        assert_eq!(Operation::Max((-4, -4).into()).reduce().unwrap(), -4);
+    }
+
+    #[test]
+    fn parse_min_max() {
+        let min: Operation = "min(3,9)".parse().unwrap();
+        assert_eq!(min, Operation::Min((3, 9).into()));
+        assert_eq!(min.to_string(), "min(3,9)");
+        let max: Operation = " max( -3 , 9 ) ".parse().unwrap();
+        assert_eq!(max, Operation::Max((-3, 9).into()));
+        assert_eq!(max.to_string(), "max(-3,9)");
+        assert!("min(3)".parse::<Operation>().is_err());
+        assert!("foo(3,9)".parse::<Operation>().is_err());
+    }
+
+    #[test]
+    fn encode_max() {
+        let encoded = Operation::Max((3, -9).into()).encode();
+        assert_eq!(encoded[..], [8u8, 2, 3, 247]);
+        let tlv: Tlv = (&encoded[..]).try_into().unwrap();
+        assert_eq!(
+            Operation::try_from(tlv).unwrap(),
+            Operation::Max((3, -9).into())
+        );
+    }
+
+    #[test]
+    fn operation_bitwise() {
+        assert_eq!(Operation::And((12, 10).into()).reduce().unwrap(), 8);
+        assert_eq!(Operation::Or((12, 10).into()).reduce().unwrap(), 14);
+        assert_eq!(Operation::Xor((12, 10).into()).reduce().unwrap(), 6);
+        assert_eq!(Operation::And((-1, 5).into()).reduce().unwrap(), 5);
+        assert_eq!(Operation::Or((-128, 1).into()).reduce().unwrap(), -127);
+        assert_eq!(Operation::Xor((-1, 1).into()).reduce().unwrap(), -2);
+    }
+
+    #[test]
+    fn parse_bitwise() {
+        assert_eq!(
+            "12 & -10".parse::<Operation>().unwrap(),
+            Operation::And((12, -10).into())
+        );
+        assert_eq!(
+            "12|10".parse::<Operation>().unwrap(),
+            Operation::Or((12, 10).into())
+        );
+        assert_eq!(
+            "12 ^^ 10".parse::<Operation>().unwrap(),
+            Operation::Xor((12, 10).into())
+        );
+        assert_eq!(
+            "12 xor 10".parse::<Operation>().unwrap(),
+            Operation::Xor((12, 10).into())
+        );
+        assert_eq!(
+            "12 x 10".parse::<Operation>().unwrap(),
+            Operation::Mul((12, 10).into())
+        );
+    }
+
+    #[test]
+    fn bitwise_round_trip() {
+        for operation in [
+            Operation::And((-3, 7).into()),
+            Operation::Or((-3, 7).into()),
+            Operation::Xor((-3, 7).into()),
+        ] {
+            let encoded = operation.clone().encode();
+            assert_eq!(Operation::try_from(&encoded[..]).unwrap(), operation);
+            assert_eq!(
+                operation.to_string().parse::<Operation>().unwrap(),
+                operation
+            );
+        }
+    }
+
+    #[test]
+    fn operation_shift() {
+        assert_eq!(Operation::Shl((3, 4).into()).reduce().unwrap(), 48);
+        assert_eq!(Operation::Shl((1, 63).into()).reduce().unwrap(), i64::MIN);
+        assert_eq!(Operation::Shr((-128, 3).into()).reduce().unwrap(), -16);
+        assert_eq!(
+            "3 << 4".parse::<Operation>().unwrap(),
+            Operation::Shl((3, 4).into())
+        );
+        assert_eq!(
+            "3>>-1".parse::<Operation>().unwrap(),
+            Operation::Shr((3, -1).into())
+        );
+    }
+
+    #[test]
+    fn operation_shift_negative() {
+        assert!(matches!(
+            Operation::Shl((3, -1).into()).reduce(),
+            Err(OperationError::WrongDomain)
+        ));
+        assert!(matches!(
+            Operation::Shr((3, -1).into()).reduce(),
+            Err(OperationError::WrongDomain)
+        ));
+    }
+
+    #[test]
+    fn operation_shift_oversized() {
+        assert!(matches!(
+            Operation::Shl((3, 64).into()).reduce(),
+            Err(OperationError::WrongDomain)
+        ));
+        assert!(matches!(
+            Operation::Shr((3, 127).into()).reduce(),
+            Err(OperationError::WrongDomain)
+        ));
+    }
+
+    #[test]
+    fn operation_isqrt() {
+        assert_eq!(Operation::Isqrt(15.into()).reduce().unwrap(), 3);
+        assert_eq!(Operation::Isqrt(16.into()).reduce().unwrap(), 4);
+        assert!(matches!(
+            Operation::Isqrt((-1).into()).reduce(),
+            Err(OperationError::WrongDomain)
+        ));
+        assert_eq!(
+            "sqrt(16)".parse::<Operation>().unwrap(),
+            Operation::Isqrt(16.into())
+        );
+    }
+
+    #[test]
+    fn operation_ilog2() {
+        assert_eq!(Operation::Ilog2(1.into()).reduce().unwrap(), 0);
+        assert_eq!(Operation::Ilog2(8.into()).reduce().unwrap(), 3);
+        assert_eq!(Operation::Ilog2(127.into()).reduce().unwrap(), 6);
+        assert!(Operation::Ilog2(0.into()).reduce().is_err());
+        assert!(Operation::Ilog2((-8).into()).reduce().is_err());
+        assert_eq!(
+            "log2(8)".parse::<Operation>().unwrap(),
+            Operation::Ilog2(8.into())
+        );
+        assert_eq!(Operation::Ilog2(8.into()).to_string(), "log2(8)");
+    }
+
+    #[test]
+    fn operation_modpow() {
+        assert_eq!(Operation::ModPow((2, 10, 100).into()).reduce().unwrap(), 24);
+        assert_eq!(Operation::ModPow((3, 0, 7).into()).reduce().unwrap(), 1);
+        assert_eq!(Operation::ModPow((3, 0, 1).into()).reduce().unwrap(), 0);
+        assert_eq!(
+            Operation::ModPow((127, 127, 127).into()).reduce().unwrap(),
+            0
+        );
+        assert_eq!(Operation::ModPow((-2, 3, 5).into()).reduce().unwrap(), -3);
+        assert!(matches!(
+            Operation::ModPow((2, 10, 0).into()).reduce(),
+            Err(OperationError::WrongDomain)
+        ));
+        assert!(matches!(
+            Operation::ModPow((2, -1, 7).into()).reduce(),
+            Err(OperationError::WrongDomain)
+        ));
+    }
+
+    #[test]
+    fn parse_modpow() {
+        let operation: Operation = "modpow(2, 10, 100)".parse().unwrap();
+        assert_eq!(operation, Operation::ModPow((2, 10, 100).into()));
+        assert_eq!(operation.to_string(), "modpow(2,10,100)");
+        assert!("modpow(2,10)".parse::<Operation>().is_err());
+        // Operands must fit in an i8
+        assert!("modpow(2,10,1000)".parse::<Operation>().is_err());
+
+        let encoded = operation.clone().encode();
+        assert_eq!(encoded[..], [28u8, 3, 2, 10, 100]);
+        assert_eq!(Operation::try_from(&encoded[..]).unwrap(), operation);
+    }
+
+    #[test]
+    fn encoded_len() {
+        let operations: Vec<Operation> = (0..=255u8)
+            .filter_map(|t| TlvType::try_from(t).ok())
+            .filter_map(|tag| {
+                let data = vec![1u8; tag.expected_length()? as usize];
+                Operation::try_from(Tlv::new(tag, &data).ok()?).ok()
+            })
+            .collect();
+        assert!(!operations.is_empty());
+        for operation in operations {
+            assert_eq!(
+                operation.encoded_len(),
+                operation.clone().encode().len(),
+                "{operation:?}"
+            );
+        }
+        assert_eq!(Operation::Sum((3, 4).into()).encoded_len(), 4);
+        assert_eq!(Operation::Fact(5.into()).encoded_len(), 3);
+    }
+
+    #[test]
+    fn parse_signs() {
+        assert_eq!(
+            "+5 * -3".parse::<Operation>().unwrap(),
+            Operation::Mul((5, -3).into())
+        );
+        assert_eq!(
+            "5 - -3".parse::<Operation>().unwrap(),
+            Operation::Sub((5, -3).into())
+        );
+        assert_eq!(
+            "5 + +3".parse::<Operation>().unwrap(),
+            Operation::Sum((5, 3).into())
+        );
+        assert_eq!(
+            "-5--3".parse::<Operation>().unwrap(),
+            Operation::Sub((-5, -3).into())
+        );
+        assert_eq!(
+            "+5!".parse::<Operation>().unwrap(),
+            Operation::Fact(5.into())
+        );
+        assert_eq!(
+            Operation::from_str_wide("+500 * -3").unwrap(),
+            Operation::WMul((500, -3).into())
+        );
+
+        assert!("5 ++ 3".parse::<Operation>().is_err());
+        assert!("5 + + 3".parse::<Operation>().is_err());
+        assert!("+ 5 + 3".parse::<Operation>().is_err());
+        assert!(Operation::from_str_wide("500 ++ 3").is_err());
+    }
+
+    #[test]
+    fn average() {
+        assert_eq!(
+            "avg(3,5)".parse::<Operation>().unwrap(),
+            Operation::Avg((3, 5).into())
+        );
+        for (a, b, mean) in [
+            (3, 5, 4),
+            (3, 4, 3),
+            (-3, -4, -3),
+            (-3, 4, 0),
+            (127, 127, 127),
+        ] {
+            let operation = Operation::Avg((a, b).into());
+            assert_eq!(operation.reduce().unwrap(), mean, "{operation}");
+        }
+        assert_eq!(
+            Operation::Avg((i8::MIN, i8::MIN).into()).reduce().unwrap(),
+            -128
+        );
+        assert_eq!(Operation::Avg((-3, -4).into()).to_string(), "avg(-3,-4)");
+    }
+
+    #[test]
+    fn popcount() {
+        for (a, bits) in [(0, 0), (-1, 8), (7, 3), (i8::MIN, 1)] {
+            let operation: Operation = format!("popcount({a})").parse().unwrap();
+            assert_eq!(operation, Operation::PopCount(a.into()));
+            assert_eq!(operation.reduce().unwrap(), bits);
+            assert_eq!(operation.to_string(), format!("popcount({a})"));
+        }
+        assert!("popcount(1,2)".parse::<Operation>().is_err());
+        assert_eq!(
+            Operation::try_from(&[32u8, 1, 0x0f][..]).unwrap(),
+            Operation::PopCount(15.into())
+        );
+    }
+
+    #[test]
+    fn rem_and_mod() {
+        assert_eq!(Operation::Rem((-7, 3).into()).reduce().unwrap(), -1);
+        assert_eq!(Operation::Mod((-7, 3).into()).reduce().unwrap(), 2);
+        assert_eq!(Operation::Mod((-7, -3).into()).reduce().unwrap(), 2);
+        assert_eq!(Operation::Mod((i8::MIN, -1).into()).reduce().unwrap(), 0);
+        assert!(matches!(
+            Operation::Mod((7, 0).into()).reduce(),
+            Err(OperationError::WrongDomain)
+        ));
+
+        let operation = Operation::Mod((-7, 3).into());
+        assert_eq!(operation.to_string(), "-7 mod 3");
+        assert_eq!(
+            operation.to_string().parse::<Operation>().unwrap(),
+            operation
+        );
+        assert_eq!(
+            Operation::try_from(&operation.clone().encode()[..]).unwrap(),
+            operation
+        );
+    }
+
+    #[test]
+    fn parse_aliases() {
+        for s in ["7 rem 3", "7Rem3", "7 % 3"] {
+            assert_eq!(
+                s.parse::<Operation>().unwrap(),
+                Operation::Rem((7, 3).into()),
+                "{s}"
+            );
+        }
+        for s in ["7 mod 3", "7 MOD 3", "7mod3"] {
+            assert_eq!(
+                s.parse::<Operation>().unwrap(),
+                Operation::Mod((7, 3).into()),
+                "{s}"
+            );
+        }
+        assert_eq!(
+            "12 XOR 10".parse::<Operation>().unwrap(),
+            Operation::Xor((12, 10).into())
+        );
+        assert_eq!(
+            "12X10".parse::<Operation>().unwrap(),
+            Operation::Mul((12, 10).into())
+        );
+        assert_eq!(
+            "Sqrt(16)".parse::<Operation>().unwrap(),
+            Operation::Isqrt(16.into())
+        );
+        assert_eq!(
+            "MIN( -3 , 9 )".parse::<Operation>().unwrap(),
+            Operation::Min((-3, 9).into())
+        );
+        assert_eq!(
+            "LOG2 (8)".parse::<Operation>().unwrap(),
+            Operation::Ilog2(8.into())
+        );
+        assert_eq!(
+            Operation::from_rpn("7 3 Mod").unwrap(),
+            Operation::Mod((7, 3).into())
+        );
+        assert_eq!(
+            Operation::from_rpn("3 9 MAX").unwrap(),
+            Operation::Max((3, 9).into())
+        );
+
+        assert!("7 modulo 3".parse::<Operation>().is_err());
+        assert!("min(3,,9)".parse::<Operation>().is_err());
+        assert!(matches!(
+            "min(3,4,)".parse::<Operation>(),
+            Err(OperationError::Parse)
+        ));
+        assert!("sqrt(4,)".parse::<Operation>().is_err());
+        assert!("min(3 9)".parse::<Operation>().is_err());
+        assert!("3 ^ 4".parse::<Operation>().is_err());
+        assert!("3 + 4 + 5".parse::<Operation>().is_err());
+        assert!(matches!(
+            "3u mod 4u".parse::<Operation>(),
+            Err(OperationError::UnsupportedOperation(_))
+        ));
+    }
+
+    #[test]
+    fn parse_rpn() {
+        assert_eq!(
+            Operation::from_rpn("3 4 +").unwrap(),
+            Operation::Sum((3, 4).into())
+        );
+        assert_eq!(
+            Operation::from_rpn(" 3 -4 - ").unwrap(),
+            Operation::Sub((3, -4).into())
+        );
+        assert_eq!(
+            Operation::from_rpn("5 !").unwrap(),
+            Operation::Fact(5.into())
+        );
+        assert_eq!(
+            Operation::from_rpn("3 9 min").unwrap(),
+            Operation::Min((3, 9).into())
+        );
+        assert_eq!(
+            Operation::from_rpn("2 10 100 modpow").unwrap(),
+            Operation::ModPow((2, 10, 100).into())
+        );
+        assert!(Operation::from_rpn("3 4 5 +").is_err());
+        assert!(Operation::from_rpn("3 + 4").is_err());
+        assert!(Operation::from_rpn("+").is_err());
+        assert!(Operation::from_rpn("").is_err());
+    }
+
+    #[test]
+    fn operation_absdiff() {
+        let operation: Operation = "absdiff(3,9)".parse().unwrap();
+        assert_eq!(operation, Operation::AbsDiff((3, 9).into()));
+        assert_eq!(operation.reduce().unwrap(), 6);
+        assert_eq!(operation.to_string(), "absdiff(3,9)");
+        assert_eq!(Operation::AbsDiff((9, 3).into()).reduce().unwrap(), 6);
+        assert_eq!(Operation::AbsDiff((-7, -7).into()).reduce().unwrap(), 0);
+        assert_eq!(
+            Operation::AbsDiff((i8::MIN, i8::MAX).into())
+                .reduce()
+                .unwrap(),
+            255
+        );
+
+        let encoded = operation.clone().encode();
+        assert_eq!(encoded[..], [29u8, 2, 3, 9]);
+        assert_eq!(Operation::try_from(&encoded[..]).unwrap(), operation);
+    }
+
+    #[test]
+    fn operation_inverse() {
+        for operation in [
+            Operation::Sum((3, 4).into()),
+            Operation::Sum((127, 127).into()),
+            Operation::Sub((-128, 127).into()),
+            Operation::Sub((10, 3).into()),
+            Operation::Mul((100, 100).into()),
+        ] {
+            let acc = 42 + operation.reduce().unwrap();
+            let inverse = operation.inverse().unwrap();
+            assert_eq!(acc + inverse.reduce().unwrap(), 42, "{operation}");
+        }
+        assert_eq!(
+            Operation::Sum((3, 4).into()).inverse(),
+            Some(Operation::Sub((0, 7).into()))
+        );
+        assert_eq!(Operation::Fact(5.into()).inverse(), None);
+        assert_eq!(Operation::Div((8, 2).into()).inverse(), None);
+        assert_eq!(Operation::Rem((8, 0).into()).inverse(), None);
+        assert_eq!(Operation::WSum((i16::MIN, 0).into()).inverse(), None);
+    }
+
+    #[test]
+    fn operation_order() {
+        let mut operations = vec![
+            Operation::Fact(3.into()),
+            Operation::Sum((3, 4).into()),
+            Operation::USum((1, 2).into()),
+            Operation::Sum((-3, 4).into()),
+            Operation::Mul((2, 5).into()),
+            Operation::Sum((-3, -4).into()),
+            Operation::ModPow((2, 3, 5).into()),
+            Operation::WSum((-300, 4).into()),
+        ];
+        operations.sort();
+        assert_eq!(
+            operations,
+            [
+                Operation::Sum((-3, -4).into()),
+                Operation::Sum((-3, 4).into()),
+                Operation::Sum((3, 4).into()),
+                Operation::Mul((2, 5).into()),
+                Operation::Fact(3.into()),
+                Operation::ModPow((2, 3, 5).into()),
+                Operation::USum((1, 2).into()),
+                Operation::WSum((-300, 4).into()),
+            ]
+        );
+
+        // One operation of each type, in the order of their tags
+        let tags: Vec<TlvType> = (0..=255u8).filter_map(|t| t.try_into().ok()).collect();
+        let operations: Vec<Operation> = tags
+            .iter()
+            .filter_map(|&tag| {
+                let data = vec![1u8; tag.expected_length()? as usize];
+                Operation::try_from(Tlv::new(tag, &data).ok()?).ok()
+            })
+            .collect();
+        assert_eq!(operations.len(), 28);
+        let mut sorted = operations.clone();
+        sorted.reverse();
+        sorted.sort();
+        assert_eq!(sorted, operations);
+    }
+
+    #[test]
+    fn decompose() {
+        for target in [
+            0,
+            1,
+            -1,
+            127,
+            253,
+            254,
+            -255,
+            1000,
+            -1000,
+            16129,
+            100_000,
+            -99_999,
+            i64::MAX,
+            i64::MIN,
+            i64::MIN + 1,
+        ] {
+            let ops = Operation::decompose_to(target);
+            assert_eq!(Operation::reduce_all(&ops, 0).0, target, "{target}");
+            assert!(ops.len() <= 10, "{target}");
+        }
+        assert!(Operation::decompose_to(0).is_empty());
+        assert_eq!(Operation::decompose_to(1000).len(), 2);
+        assert_eq!(
+            Operation::decompose_to(i64::MIN),
+            [Operation::Shl((-1, 63).into())]
+        );
+    }
+
+    #[test]
+    fn operation_cost() {
+        assert_eq!(Operation::Fact(10.into()).cost(), 10);
+        assert_eq!(Operation::Fact((-3).into()).cost(), 0);
+        assert_eq!(Operation::Sum((100, 27).into()).cost(), 1);
+        assert_eq!(Operation::Div((100, 27).into()).cost(), 1);
+        assert_eq!(Operation::ModPow((2, 100, 7).into()).cost(), 7);
+        assert_eq!(Operation::ModPow((2, 0, 7).into()).cost(), 1);
+    }
+
+    #[test]
+    fn operation_wide() {
+        let operation = Operation::from_str_wide("100 * -300").unwrap();
+        assert_eq!(operation, Operation::WMul((100, -300).into()));
+        assert_eq!(operation.reduce().unwrap(), -30000);
+        assert_eq!(operation.to_string(), "100×-300");
+
+        let encoded = operation.clone().encode();
+        assert_eq!(encoded[..], [66u8, 4, 0x00, 0x64, 0xfe, 0xd4]);
+        assert_eq!(Operation::try_from(&encoded[..]).unwrap(), operation);
+
+        assert!(Operation::WDiv((1, 0).into()).reduce().is_err());
+        assert!(Operation::from_str_wide("40000+1").is_err());
+        assert!(Operation::from_str_wide("5!").is_err());
+        assert!(Operation::from_str_wide("500 % 3").is_err());
+        assert_eq!(
+            Operation::from_str_wide("500 x 3").unwrap(),
+            Operation::WMul((500, 3).into())
+        );
+    }
+
+    #[test]
+    fn operation_unsigned() {
+        let operation: Operation = "200u + 100u".parse().unwrap();
+        assert_eq!(operation, Operation::USum((200, 100).into()));
+        assert_eq!(operation.reduce().unwrap(), 300);
+        assert_eq!(operation.to_string(), "200u+100u");
+
+        // The same bytes are -56 and 100 when signed
+        let encoded = operation.encode();
+        assert_eq!(encoded[..], [60u8, 2, 200, 100]);
+        let signed = Operation::try_from(&[1u8, 2, 200, 100][..]).unwrap();
+        assert_eq!(signed, Operation::Sum((-56, 100).into()));
+        assert_eq!(signed.reduce().unwrap(), 44);
+
+        assert_eq!(Operation::UMul((255, 255).into()).reduce().unwrap(), 65025);
+        assert_eq!(Operation::USub((1, 255).into()).reduce().unwrap(), -254);
+        assert!(Operation::UDiv((1, 0).into()).reduce().is_err());
+        assert!("200u + 100".parse::<Operation>().is_err());
+        assert!("-1u + 100u".parse::<Operation>().is_err());
+        assert!("5u!".parse::<Operation>().is_err());
+    }
+
+    #[test]
+    fn float_operation_mul() {
+        let operation: FloatOperation = "3.5*2".parse().unwrap();
+        assert_eq!(operation, FloatOperation::Mul((3.5, 2.0).into()));
+        assert_eq!(operation.reduce().unwrap(), 7.0);
+        assert_eq!(
+            "-1.25 x 4".parse::<FloatOperation>().unwrap(),
+            FloatOperation::Mul((-1.25, 4.0).into())
+        );
+        assert!("3.5*2".parse::<Operation>().is_err());
+        assert!("3. * 2".parse::<FloatOperation>().is_err());
+    }
+
+    #[test]
+    fn float_operation_round_trip() {
+        let encoded = FloatOperation::Mul((3.5, 2.0).into()).encode();
+        assert_eq!(encoded[..], [52u8, 8, 0x40, 0x60, 0, 0, 0x40, 0, 0, 0]);
+
+        let tlv: Tlv = (&encoded[..]).try_into().unwrap();
+        let operation: FloatOperation = tlv.try_into().unwrap();
+        assert_eq!(operation.reduce().unwrap(), 7.0);
+    }
+
+    #[test]
+    fn float_operation_div_zero() {
+        assert!(FloatOperation::Div((1.5, 0.0).into()).reduce().is_err());
+    }
 }
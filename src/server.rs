@@ -0,0 +1,509 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::{
+    compute_frame, verify_checksum, Answer, AnswerOrder, Lang, Numberi64, Operation, TCPLibError,
+    Tlv, TlvBuilder, TlvIterator, TlvType,
+};
+
+/// Number of operations computed of each type
+pub type Stats = HashMap<TlvType, u64>;
+
+/// How the server answers the frames it receives
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReplyOptions {
+    pub order: AnswerOrder,
+    /// Language of the error messages
+    pub lang: Lang,
+    /// Join the answers to the operations of a frame in a single answer
+    pub batch: bool,
+    /// Reject the frames not ending with a valid checksum
+    pub require_checksum: bool,
+    /// Answer with the text of the operations instead of computing them
+    pub echo: bool,
+    /// Include in each answer the change of the accumulator
+    pub delta: bool,
+    /// Include in each answer the result of the operation on its own
+    pub op_result: bool,
+}
+
+/// What the server sends back for a frame, and what it has to report
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Reply {
+    /// The encoded answers, followed by the answers to control TLVs
+    pub bytes: Vec<u8>,
+    /// Problems found while answering, such as failed operations
+    pub errors: Vec<String>,
+    /// Summary of the operations computed, if the client asked for it
+    pub stats: Option<String>,
+}
+
+/// Adds `operation` to the number of operations computed of its type
+pub fn count_operation(stats: &Mutex<Stats>, operation: &Operation) {
+    *stats.lock().unwrap().entry(operation.tag()).or_insert(0) += 1;
+}
+
+/// Computes the reply of the server to `frame`, as set by `options`, adding
+/// the results of the operations to `acc` and counting them in `stats`.
+/// `on_op` is called with every operation computed and its result. Fails
+/// only if the answers cannot be encoded.
+pub fn reply_frame(
+    frame: &[u8],
+    acc: &mut i64,
+    options: &ReplyOptions,
+    stats: &Mutex<Stats>,
+    mut on_op: impl FnMut(&Operation, i64),
+) -> Result<Reply, TCPLibError> {
+    if let Some(Err(e)) = options.require_checksum.then(|| verify_checksum(frame)) {
+        return Ok(Reply {
+            bytes: encode_answers(vec![Answer::error(*acc, e.to_string())], options.order)?,
+            errors: vec![format!("Discarding frame. {e}")],
+            stats: None,
+        });
+    }
+    if options.echo {
+        return Ok(Reply {
+            bytes: encode_answers(echo_frame(frame, *acc), options.order)?,
+            ..Default::default()
+        });
+    }
+
+    let start = *acc;
+    let mut answers = compute_frame(frame, acc, options.lang, |operation, result| {
+        count_operation(stats, operation);
+        on_op(operation, result);
+    });
+    let evaluated = evaluations(frame);
+    if options.delta {
+        add_deltas(&mut answers, &evaluated, start);
+    }
+    if !options.op_result {
+        // The sum is all there is in the answer to an Evaluate TLV
+        answers
+            .iter_mut()
+            .zip(&evaluated)
+            .filter(|(_, evaluated)| !**evaluated)
+            .for_each(|(answer, _)| answer.result = None);
+    }
+    let errors = answers
+        .iter()
+        .filter_map(|answer| answer.message.as_ref())
+        .map(|message| format!("Could not calculate answer. {message}"))
+        .collect();
+
+    // Answers to Evaluate TLVs follow the batch on their own, so that their
+    // sums are kept
+    let answers = match options.batch {
+        true => {
+            let (sums, answers): (Vec<_>, Vec<_>) = answers
+                .into_iter()
+                .zip(evaluated)
+                .partition(|(_, evaluated)| *evaluated);
+            let mut answers = merge(answers.into_iter().map(|(answer, _)| answer).collect())?;
+            answers.extend(sums.into_iter().map(|(sum, _)| sum));
+            answers
+        }
+        false => answers,
+    };
+
+    let mut reply = Reply {
+        bytes: encode_answers(answers, options.order)?,
+        errors,
+        stats: None,
+    };
+    let mut tlvs = TlvIterator::process(frame);
+    for tlv in tlvs.by_ref() {
+        match tlv.tag {
+            TlvType::Ping => reply
+                .bytes
+                .extend_from_slice(&Tlv::new(TlvType::Pong, &[]).unwrap().encode()),
+            TlvType::Stats => {
+                let stats = stats.lock().unwrap();
+                reply.stats = Some(stats_summary(&stats));
+                reply.bytes.extend_from_slice(&stats_reply(&stats));
+            }
+            _ => {}
+        }
+    }
+    if tlvs.position() < frame.len() {
+        reply.errors.push(format!(
+            "Malformed frame, stopped at byte {} of {}",
+            tlvs.position(),
+            frame.len()
+        ));
+    }
+    Ok(reply)
+}
+
+/// Answers every operation in `frame` with its text, keeping `acc` as is
+fn echo_frame(frame: &[u8], acc: i64) -> Vec<Answer> {
+    TlvIterator::process(frame)
+        .filter(|tlv| !tlv.tag.is_control())
+        .map(|tlv| match Operation::try_from(tlv) {
+            Ok(operation) => Answer::error(acc, operation.to_string()),
+            Err(e) => Answer::error(acc, e.to_string()),
+        })
+        .collect()
+}
+
+/// Encodes `answers` one after the other
+fn encode_answers(answers: Vec<Answer>, order: AnswerOrder) -> Result<Vec<u8>, TCPLibError> {
+    let mut encoded = Vec::new();
+    for answer in answers {
+        encoded.extend_from_slice(&answer.encode(order)?);
+    }
+    Ok(encoded)
+}
+
+/// Tells which of the answers to `frame` are for Evaluate TLVs
+fn evaluations(frame: &[u8]) -> Vec<bool> {
+    TlvIterator::process(frame)
+        .filter(|tlv| !tlv.tag.is_control())
+        .map(|tlv| tlv.tag == TlvType::Evaluate)
+        .collect()
+}
+
+/// Adds to the successful answers the change of the accumulator since the
+/// previous one, starting at `start`. Answers to Evaluate TLVs, flagged in
+/// `evaluated`, do not change it and get no delta.
+fn add_deltas(answers: &mut [Answer], evaluated: &[bool], start: i64) {
+    let mut previous = start;
+    for (answer, _) in answers
+        .iter_mut()
+        .zip(evaluated)
+        .filter(|(_, evaluated)| !**evaluated)
+    {
+        let acc = answer.acc.0;
+        if answer.message.is_none() {
+            answer.delta = Some(acc.saturating_sub(previous).into());
+        }
+        previous = acc;
+    }
+}
+
+/// Joins the answers to several operations in batch answers, as few as
+/// needed to fit all the accumulator values. The message of the last failed
+/// operation goes in the final one, which holds fewer values to make room
+/// for it.
+fn merge(answers: Vec<Answer>) -> Result<Vec<Answer>, TCPLibError> {
+    let accs: Vec<_> = answers.iter().map(|answer| answer.acc.0).collect();
+    let message = answers.into_iter().rev().find_map(|answer| answer.message);
+
+    let room = match &message {
+        Some(message) => {
+            (u8::MAX as usize - 2 - message.to_string().len()) / (2 + Numberi64::WIRE_LEN)
+        }
+        None => Answer::MAX_VALUES,
+    };
+    let mut chunks: Vec<_> = accs.chunks(Answer::MAX_VALUES).collect();
+    if let Some(last) = chunks.pop() {
+        let (head, tail) = last.split_at(last.len().saturating_sub(room));
+        chunks.extend([head, tail].into_iter().filter(|chunk| !chunk.is_empty()));
+    }
+
+    let mut answers = chunks
+        .into_iter()
+        .map(|chunk| Answer::try_from((chunk.to_vec(), None)))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(answer) = answers.last_mut() {
+        answer.message = message;
+    }
+    Ok(answers)
+}
+
+/// Largest number of operation types in each Stats TLV, so that they fit
+/// along with the total
+const MAX_STATS_ENTRIES: usize = (255 - 10) / 11;
+
+/// Encodes the number of operations computed as Stats TLVs, as many as
+/// needed to hold a Count TLV for every operation type, with the tag of the
+/// type followed by their count. Each Stats TLV starts with a Numi64 with the
+/// total, so that a client knows it has all the counts once they add up to
+/// it.
+fn stats_reply(stats: &Stats) -> Box<[u8]> {
+    let total: u64 = stats.values().sum();
+    let mut counts: Vec<_> = stats.iter().collect();
+    counts.sort_by_key(|&(&tag, &count)| (std::cmp::Reverse(count), u8::from(tag)));
+
+    // A Stats TLV with just the total if nothing has been computed yet
+    let mut reply = Vec::new();
+    for chunk in counts
+        .chunks(MAX_STATS_ENTRIES)
+        .chain(counts.is_empty().then_some(&[][..]))
+    {
+        let mut builder = TlvBuilder::new();
+        builder.push(TlvType::Numi64, &total.to_be_bytes()).unwrap();
+        for (&tag, count) in chunk {
+            let count = [&[u8::from(tag)][..], &count.to_be_bytes()].concat();
+            builder.push(TlvType::Count, &count).unwrap();
+        }
+        reply.extend_from_slice(&builder.wrap(TlvType::Stats).unwrap());
+    }
+    reply.into()
+}
+
+/// Human readable summary of the operations computed
+pub fn stats_summary(stats: &Stats) -> String {
+    let mut counts: Vec<_> = stats
+        .iter()
+        .map(|(tag, count)| format!("{tag:?} {count}"))
+        .collect();
+    counts.sort();
+    format!(
+        "{} operations computed. {}",
+        stats.values().sum::<u64>(),
+        counts.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use crate::{
+        append_checksum, reply_frame, Answer, AnswerOrder, Frame, Operation, Reply, ReplyOptions,
+        Stats, Tlv, TlvIterator, TlvType,
+    };
+
+    use super::{merge, stats_reply, MAX_STATS_ENTRIES};
+
+    /// The answers in `reply`, leaving out those to control TLVs
+    fn answers(reply: &Reply) -> Vec<Answer> {
+        TlvIterator::process(&reply.bytes)
+            .filter(|tlv| tlv.tag == TlvType::Answer)
+            .map(|tlv| Answer::try_from(tlv).unwrap())
+            .collect()
+    }
+
+    fn sum_and_mul() -> Box<[u8]> {
+        Frame::from(vec![
+            Operation::Sum((3, 4).into()),
+            Operation::Mul((2, 5).into()),
+        ])
+        .encode()
+    }
+
+    #[test]
+    fn reply_operations() {
+        let stats = Mutex::new(Stats::new());
+        let mut acc = 1;
+        let mut seen = vec![];
+        let reply = reply_frame(
+            &sum_and_mul(),
+            &mut acc,
+            &ReplyOptions::default(),
+            &stats,
+            |_, result| seen.push(result),
+        )
+        .unwrap();
+
+        assert_eq!(acc, 18);
+        assert_eq!(seen, [7, 10]);
+        assert_eq!(answers(&reply), [Answer::ok(8), Answer::ok(18)]);
+        assert!(reply.errors.is_empty());
+        assert_eq!(reply.stats, None);
+        let stats = stats.lock().unwrap();
+        assert_eq!((stats[&TlvType::Sum], stats[&TlvType::Mul]), (1, 1));
+    }
+
+    #[test]
+    fn reply_options() {
+        let stats = Mutex::new(Stats::new());
+        let reply = |options: &ReplyOptions| {
+            let mut acc = 1;
+            answers(&reply_frame(&sum_and_mul(), &mut acc, options, &stats, |_, _| {}).unwrap())
+        };
+
+        let batch = ReplyOptions {
+            batch: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            reply(&batch),
+            [Answer::try_from((vec![8, 18], None)).unwrap()]
+        );
+
+        let delta = ReplyOptions {
+            delta: true,
+            op_result: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            reply(&delta),
+            [
+                Answer::ok(8).with_delta(7).with_result(7),
+                Answer::ok(18).with_delta(10).with_result(10)
+            ]
+        );
+
+        let echo = ReplyOptions {
+            echo: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            reply(&echo),
+            [Answer::error(1, "3+4"), Answer::error(1, "2×5")]
+        );
+        assert_eq!(stats.lock().unwrap()[&TlvType::Sum], 2);
+    }
+
+    #[test]
+    fn reply_evaluate_in_batch() {
+        let evaluate = Tlv::new(TlvType::Evaluate, &sum_and_mul())
+            .unwrap()
+            .encode();
+        let frame = [&Operation::Sum((1, 1).into()).encode()[..], &evaluate].concat();
+        let options = ReplyOptions {
+            batch: true,
+            ..Default::default()
+        };
+
+        let mut acc = 1;
+        let stats = Mutex::new(Stats::new());
+        let reply = reply_frame(&frame, &mut acc, &options, &stats, |_, _| {}).unwrap();
+        assert_eq!(
+            answers(&reply),
+            [Answer::ok(3), Answer::ok(3).with_result(17)]
+        );
+        assert_eq!(acc, 3);
+    }
+
+    #[test]
+    fn reply_checksum() {
+        let options = ReplyOptions {
+            require_checksum: true,
+            order: AnswerOrder::MessageLast,
+            ..Default::default()
+        };
+        let stats = Mutex::new(Stats::new());
+        let mut acc = 1;
+
+        let reply = reply_frame(&sum_and_mul(), &mut acc, &options, &stats, |_, _| {}).unwrap();
+        assert_eq!(
+            answers(&reply),
+            [Answer::error(1, "Missing or wrong checksum")]
+        );
+        assert_eq!(reply.errors.len(), 1);
+        assert!(stats.lock().unwrap().is_empty());
+
+        let frame = append_checksum(&sum_and_mul());
+        let reply = reply_frame(&frame, &mut acc, &options, &stats, |_, _| {}).unwrap();
+        assert_eq!(answers(&reply), [Answer::ok(8), Answer::ok(18)]);
+        assert!(reply.errors.is_empty());
+    }
+
+    #[test]
+    fn reply_control() {
+        let frame = [
+            &Tlv::new(TlvType::Ping, &[]).unwrap().encode()[..],
+            &Operation::Div((1, 0).into()).encode(),
+            &Tlv::new(TlvType::Stats, &[]).unwrap().encode(),
+            &Operation::Sum((3, 4).into()).encode(),
+            &[1, 5, 0],
+        ]
+        .concat();
+
+        let mut acc = 0;
+        let stats = Mutex::new(Stats::new());
+        let reply = reply_frame(
+            &frame,
+            &mut acc,
+            &ReplyOptions::default(),
+            &stats,
+            |_, _| {},
+        )
+        .unwrap();
+        let tags: Vec<_> = TlvIterator::process(&reply.bytes)
+            .map(|tlv| tlv.tag)
+            .collect();
+        assert_eq!(
+            tags,
+            [
+                TlvType::Answer,
+                TlvType::Answer,
+                TlvType::Pong,
+                TlvType::Stats
+            ]
+        );
+        assert_eq!(reply.stats.unwrap(), "1 operations computed. Sum 1");
+        assert_eq!(reply.errors.len(), 2);
+        assert!(reply.errors[0].starts_with("Could not calculate answer."));
+        assert_eq!(reply.errors[1], "Malformed frame, stopped at byte 12 of 15");
+    }
+
+    #[test]
+    fn stats_in_several_tlvs() {
+        let stats: Stats = (0..=u8::MAX)
+            .filter_map(|tag| TlvType::try_from(tag).ok())
+            .zip(1..=30)
+            .collect();
+        assert_eq!(stats.len(), 30);
+        let total: u64 = stats.values().sum();
+
+        let reply = stats_reply(&stats);
+        let mut counts = Stats::new();
+        let mut tlvs = TlvIterator::process(&reply);
+        for tlv in tlvs.by_ref() {
+            assert_eq!(tlv.tag, TlvType::Stats);
+            assert!(tlv.children().count() <= 1 + MAX_STATS_ENTRIES);
+            let mut children = tlv.children();
+            let sum = children.next().unwrap();
+            assert_eq!(u64::from_be_bytes(sum.fixed::<8>().unwrap()), total);
+            for count in children {
+                let [tag, count @ ..] = count.fixed::<9>().unwrap();
+                counts.insert(TlvType::try_from(tag).unwrap(), u64::from_be_bytes(count));
+            }
+        }
+        assert_eq!(tlvs.position(), reply.len());
+        assert_eq!(counts, stats);
+
+        let empty = stats_reply(&Stats::new());
+        assert_eq!(empty[..4], [u8::from(TlvType::Stats), 10, 16, 8]);
+        assert_eq!(empty.len(), 12);
+    }
+
+    #[test]
+    fn merge_long_batch() {
+        let answers: Vec<_> = (1..=30).map(Answer::ok).collect();
+        let merged = merge(answers).unwrap();
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].values().count(), Answer::MAX_VALUES);
+        let values: Vec<_> = merged
+            .iter()
+            .flat_map(Answer::values)
+            .map(|v| v.0)
+            .collect();
+        assert_eq!(values, (1..=30).collect::<Vec<_>>());
+
+        let mut answers: Vec<_> = (1..=30).map(Answer::ok).collect();
+        answers[3] = Answer::error(3, "x".repeat(300));
+        let merged = merge(answers).unwrap();
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged[2].values().count(), 1);
+        assert!(merged[2].message.is_some());
+        for answer in merged {
+            assert!(answer.encode(AnswerOrder::MessageLast).unwrap().len() <= 2 + 255);
+        }
+    }
+}
@@ -20,33 +20,163 @@
  *
  */
 
-use std::num::TryFromIntError;
+use std::{fmt::Write, io, num::TryFromIntError};
 
 use num_enum::{IntoPrimitive, TryFromPrimitive, TryFromPrimitiveError};
 use thiserror::Error;
 
+use crate::{operation::OperationError, Operation};
+
 #[derive(Clone, Error, Debug)]
 pub enum TlvError {
-    #[error("Unknown tag")]
+    #[error("Unknown tag {}", .0.number)]
     TagUnknown(#[from] TryFromPrimitiveError<TlvType>),
     #[error("Wrong format for tag")]
     WrongFormat,
     #[error("Too much data to be encoded")]
     ExcessiveLength(#[from] TryFromIntError),
+    #[error("Unknown operator {0}")]
+    UnknownOperator(String),
 }
 
-#[derive(Debug, PartialEq, Eq, IntoPrimitive, TryFromPrimitive)]
+/// Types of TLV. New types may be added in minor releases, so matches on
+/// them need a wildcard arm.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, IntoPrimitive, TryFromPrimitive)]
 #[repr(u8)]
+#[non_exhaustive]
 pub enum TlvType {
+    Pad = 0,
     Sum = 1,
     Sub = 2,
     Mul = 3,
     Div = 4,
     Rem = 5,
     Fact = 6,
+    Min = 7,
+    Max = 8,
+    Mod = 9,
     Answer = 10,
     Invalid = 11,
+    Evaluate = 12,
     Numi64 = 16,
+    Delta = 17,
+    Numf64 = 18,
+    OpResult = 19,
+    Checksum = 20,
+    And = 21,
+    Or = 22,
+    Xor = 23,
+    Shl = 24,
+    Shr = 25,
+    Isqrt = 26,
+    Ilog2 = 27,
+    ModPow = 28,
+    AbsDiff = 29,
+    Ping = 30,
+    Pong = 31,
+    PopCount = 32,
+    Avg = 33,
+    Version = 40,
+    Stats = 41,
+    Count = 42,
+    SumF = 50,
+    SubF = 51,
+    MulF = 52,
+    DivF = 53,
+    USum = 60,
+    USub = 61,
+    UMul = 62,
+    UDiv = 63,
+    WSum = 64,
+    WSub = 65,
+    WMul = 66,
+    WDiv = 67,
+}
+
+impl TlvType {
+    /// Type of the signed operation written with the `symbol` operator,
+    /// such as `+` for [`TlvType::Sum`]. Operators written as words, like
+    /// `x` or `xor`, must be in lowercase.
+    pub fn from_operator(symbol: &str) -> Result<Self, TlvError> {
+        Ok(match symbol {
+            "+" => TlvType::Sum,
+            "-" => TlvType::Sub,
+            "*" | "×" | "x" => TlvType::Mul,
+            "/" | "÷" => TlvType::Div,
+            "%" | "rem" => TlvType::Rem,
+            "mod" => TlvType::Mod,
+            "!" => TlvType::Fact,
+            "&" => TlvType::And,
+            "|" => TlvType::Or,
+            "^^" | "xor" => TlvType::Xor,
+            "<<" => TlvType::Shl,
+            ">>" => TlvType::Shr,
+            _ => return Err(TlvError::UnknownOperator(symbol.into())),
+        })
+    }
+
+    /// Whether TLVs of this type control the connection instead of carrying
+    /// an operation
+    pub fn is_control(&self) -> bool {
+        matches!(
+            self,
+            TlvType::Pad
+                | TlvType::Checksum
+                | TlvType::Ping
+                | TlvType::Pong
+                | TlvType::Version
+                | TlvType::Stats
+        )
+    }
+
+    /// Data length of the TLVs of this type, or `None` if it is variable
+    pub fn expected_length(&self) -> Option<u8> {
+        match self {
+            TlvType::Sum
+            | TlvType::Sub
+            | TlvType::Mul
+            | TlvType::Div
+            | TlvType::Rem
+            | TlvType::Mod
+            | TlvType::Min
+            | TlvType::Max
+            | TlvType::And
+            | TlvType::Or
+            | TlvType::Xor
+            | TlvType::Shl
+            | TlvType::Shr
+            | TlvType::AbsDiff
+            | TlvType::Avg
+            | TlvType::Checksum
+            | TlvType::USum
+            | TlvType::USub
+            | TlvType::UMul
+            | TlvType::UDiv => Some(2),
+            TlvType::Fact | TlvType::Isqrt | TlvType::Ilog2 | TlvType::PopCount => Some(1),
+            TlvType::ModPow => Some(3),
+            TlvType::WSum | TlvType::WSub | TlvType::WMul | TlvType::WDiv => Some(4),
+            TlvType::Pad | TlvType::Ping | TlvType::Pong => Some(0),
+            TlvType::Version => Some(1),
+            TlvType::Count => Some(9),
+            TlvType::Numi64
+            | TlvType::Delta
+            | TlvType::OpResult
+            | TlvType::Numf64
+            | TlvType::SumF
+            | TlvType::SubF
+            | TlvType::MulF
+            | TlvType::DivF => Some(8),
+            TlvType::Answer | TlvType::Invalid | TlvType::Evaluate | TlvType::Stats => None,
+        }
+    }
+}
+
+impl TryFrom<&str> for TlvType {
+    type Error = TlvError;
+
+    fn try_from(symbol: &str) -> Result<Self, Self::Error> {
+        TlvType::from_operator(symbol)
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -65,8 +195,57 @@ impl<'a> Tlv<'a> {
         })
     }
 
+    /// Checks that the length matches the one expected for the tag
+    pub fn is_well_formed(&self) -> bool {
+        self.tag
+            .expected_length()
+            .is_none_or(|length| length == self.length)
+    }
+
+    /// The data as an array of exactly `N` bytes, such as 8 for a Numi64.
+    /// Fails with [`TlvError::WrongFormat`] if the length is not `N`.
+    pub fn fixed<const N: usize>(&self) -> Result<[u8; N], TlvError> {
+        match self.data.try_into() {
+            Ok(data) if self.length as usize == N => Ok(data),
+            _ => Err(TlvError::WrongFormat),
+        }
+    }
+
+    /// Iterates over the TLVs nested in the data of this one
+    pub fn children(&self) -> TlvIterator<'a> {
+        TlvIterator::process(self.data)
+    }
+
+    /// Iterates over the tag and data of the TLVs nested in the data of this
+    /// one, including those with a tag unknown to this library
+    pub fn raw_children(&self) -> RawTlvIterator<'a> {
+        RawTlvIterator(self.data)
+    }
+
+    /// Parses `bytes` as a single TLV, failing if there are bytes beyond its
+    /// declared length
+    pub fn try_from_exact(bytes: &'a [u8]) -> Result<Self, TlvError> {
+        let tlv = Tlv::try_from(bytes)?;
+        match bytes.len() == 2 + tlv.length as usize {
+            true => Ok(tlv),
+            false => Err(TlvError::WrongFormat),
+        }
+    }
+
+    /// The tag and length bytes that start a TLV, usable to build constant
+    /// frames
+    pub const fn encoded_header(tag: TlvType, length: u8) -> [u8; 2] {
+        [tag as u8, length]
+    }
+
+    /// Writes the encoded TLV to `w` without allocating
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&Self::encoded_header(self.tag, self.length))?;
+        w.write_all(self.data)
+    }
+
     pub fn encode(self) -> Box<[u8]> {
-        [self.tag.into(), self.length]
+        Self::encoded_header(self.tag, self.length)
             .iter()
             .chain(self.data)
             .copied()
@@ -79,24 +258,138 @@ impl<'a> TryFrom<&'a [u8]> for Tlv<'a> {
 
     fn try_from(bytes: &'a [u8]) -> Result<Self, Self::Error> {
         match bytes.len() {
-            2.. if bytes.len() >= (bytes[1] + 2).into() => Ok(Tlv {
+            2.. if bytes.len() >= bytes[1] as usize + 2 => Ok(Tlv {
                 tag: bytes[0].try_into()?,
                 length: bytes[1],
-                data: &bytes[2..(2 + bytes[1]).into()],
+                data: &bytes[2..bytes[1] as usize + 2],
             }),
             _ => Err(TlvError::WrongFormat),
         }
     }
 }
 
+#[derive(Debug, Default)]
+pub struct TlvBuilder {
+    buf: Vec<u8>,
+}
+
+impl TlvBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, tag: TlvType, data: &[u8]) -> Result<&mut Self, TlvError> {
+        self.push_raw(tag.into(), data)
+    }
+
+    /// Like [`TlvBuilder::push`], but taking any tag, known or not
+    pub fn push_raw(&mut self, tag: u8, data: &[u8]) -> Result<&mut Self, TlvError> {
+        let length = data.len().try_into()?;
+        self.buf.extend_from_slice(&[tag, length]);
+        self.buf.extend_from_slice(data);
+        Ok(self)
+    }
+
+    /// Concatenation of all the pushed TLVs
+    pub fn encode(&self) -> Box<[u8]> {
+        self.buf.as_slice().into()
+    }
+
+    /// A single TLV of type `tag` containing all the pushed TLVs
+    pub fn wrap(&self, tag: TlvType) -> Result<Box<[u8]>, TlvError> {
+        Ok(Tlv::new(tag, &self.buf)?.encode())
+    }
+}
+
+/// Formats bytes as space separated two-digit hexadecimal numbers
+pub fn hexdump(bytes: &[u8]) -> String {
+    let mut dump = String::with_capacity(3 * bytes.len());
+    for byte in bytes {
+        if !dump.is_empty() {
+            dump.push(' ');
+        }
+        write!(dump, "{:02x}", byte).unwrap();
+    }
+    dump
+}
+
+/// Like [`hexdump`], but marking the tag and length of the TLVs found in
+/// `bytes`, as in `Sum[01 02] 01 02`. Any trailing bytes that do not form
+/// a complete TLV are dumped as is.
+pub fn hexdump_annotated(bytes: &[u8]) -> String {
+    let mut dump = String::with_capacity(4 * bytes.len());
+    let mut iterator = TlvIterator::process(bytes);
+    for tlv in iterator.by_ref() {
+        if !dump.is_empty() {
+            dump.push(' ');
+        }
+        write!(
+            dump,
+            "{:?}[{:02x} {:02x}]",
+            tlv.tag,
+            u8::from(tlv.tag),
+            tlv.length
+        )
+        .unwrap();
+        for byte in tlv.data {
+            write!(dump, " {:02x}", byte).unwrap();
+        }
+    }
+
+    let rest = iterator.remaining();
+    if !rest.is_empty() {
+        if !dump.is_empty() {
+            dump.push(' ');
+        }
+        dump.push_str(&hexdump(rest));
+    }
+    dump
+}
+
 pub struct TlvIterator<'a> {
     buf: &'a [u8],
     index: usize,
+    limit: usize,
 }
 
 impl<'a> TlvIterator<'a> {
     pub fn process(buf: &'a [u8]) -> Self {
-        Self { buf, index: 0 }
+        Self::with_limit(buf, usize::MAX)
+    }
+
+    /// Like [`TlvIterator::process`], but stopping after `max_tlvs` TLVs
+    pub fn with_limit(buf: &'a [u8], max_tlvs: usize) -> Self {
+        Self {
+            buf,
+            index: 0,
+            limit: max_tlvs,
+        }
+    }
+
+    /// The bytes not yet processed
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.buf[self.index..]
+    }
+
+    /// Offset in the buffer of the next TLV to process
+    pub fn position(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the next TLV without advancing the iterator
+    pub fn peek(&self) -> Option<Tlv<'a>> {
+        match self.limit {
+            0 => None,
+            _ => Tlv::try_from(self.remaining()).ok(),
+        }
+    }
+
+    /// Decodes every TLV as an [`Operation`], skipping padding and other
+    /// control TLVs. Use `flatten` to skip the ones that are not valid
+    /// operations either.
+    pub fn operations(self) -> impl Iterator<Item = Result<Operation, OperationError>> + 'a {
+        self.filter(|tlv| !tlv.tag.is_control())
+            .map(Operation::try_from)
     }
 }
 
@@ -104,19 +397,40 @@ impl<'a> Iterator for TlvIterator<'a> {
     type Item = Tlv<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match Tlv::try_from(&self.buf[self.index..]) {
-            Ok(tlv) => {
-                self.index += 2 + tlv.length as usize;
-                Some(tlv)
+        let tlv = self.peek()?;
+        self.index += 2 + tlv.length as usize;
+        self.limit -= 1;
+        Some(tlv)
+    }
+}
+
+/// Iterator over the tag and data of the TLVs in a buffer, whatever their
+/// tag. It stops at the first TLV longer than the bytes left.
+#[derive(Debug, Clone)]
+pub struct RawTlvIterator<'a>(&'a [u8]);
+
+impl<'a> Iterator for RawTlvIterator<'a> {
+    type Item = (u8, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match *self.0 {
+            [tag, length, ref rest @ ..] if rest.len() >= length as usize => {
+                let (data, rest) = rest.split_at(length as usize);
+                self.0 = rest;
+                Some((tag, data))
             }
-            Err(_) => None,
+            _ => None,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{Tlv, TlvIterator};
+    use crate::{
+        hexdump, hexdump_annotated,
+        tlv::{TlvBuilder, TlvError, TlvType},
+        Answer, Operation, Tlv, TlvIterator, XorShift,
+    };
 
     #[test]
     fn parse_tlv_err_long() {
@@ -126,10 +440,310 @@ mod tests {
 
     #[test]
     fn parse_tlv_err_type() {
-        let tlv: Result<Tlv, _> = (&[20u8, 8, 0, 0, 0, 0, 0, 0, 0, 1][..]).try_into();
+        let tlv: Result<Tlv, _> = (&[255u8, 8, 0, 0, 0, 0, 0, 0, 0, 1][..]).try_into();
         assert!(tlv.is_err());
     }
 
+    #[test]
+    fn parse_tlv_future_tags() {
+        // Tags not assigned yet are rejected, both alone and nested
+        for tag in (0..=255u8).filter(|&tag| TlvType::try_from(tag).is_err()) {
+            assert!(matches!(
+                Tlv::try_from(&[tag, 1, 0][..]),
+                Err(TlvError::TagUnknown(_))
+            ));
+            assert!(Operation::try_from(&[tag, 2, 0, 0][..]).is_err());
+            assert_eq!(TlvIterator::process(&[1u8, 2, 3, 4, tag, 0]).count(), 1);
+        }
+    }
+
+    #[test]
+    fn parse_tlv_err_tag_message() {
+        let error = Tlv::try_from(&[200u8, 1, 0][..]).unwrap_err();
+        assert!(matches!(error, TlvError::TagUnknown(_)));
+        assert_eq!(error.to_string(), "Unknown tag 200");
+    }
+
+    #[test]
+    fn tlv_type_from_operator() {
+        for (symbol, tag) in [
+            ("+", TlvType::Sum),
+            ("-", TlvType::Sub),
+            ("*", TlvType::Mul),
+            ("×", TlvType::Mul),
+            ("x", TlvType::Mul),
+            ("/", TlvType::Div),
+            ("÷", TlvType::Div),
+            ("%", TlvType::Rem),
+            ("rem", TlvType::Rem),
+            ("mod", TlvType::Mod),
+            ("!", TlvType::Fact),
+            ("&", TlvType::And),
+            ("|", TlvType::Or),
+            ("^^", TlvType::Xor),
+            ("xor", TlvType::Xor),
+            ("<<", TlvType::Shl),
+            (">>", TlvType::Shr),
+        ] {
+            assert_eq!(TlvType::from_operator(symbol).unwrap(), tag);
+            assert_eq!(TlvType::try_from(symbol).unwrap(), tag);
+        }
+
+        for symbol in ["", "^", "X", "++", "min", "modulo"] {
+            assert!(matches!(
+                TlvType::from_operator(symbol),
+                Err(TlvError::UnknownOperator(s)) if s == symbol
+            ));
+        }
+    }
+
+    #[test]
+    fn tlv_const_header() {
+        const PING: [u8; 2] = Tlv::encoded_header(TlvType::Ping, 0);
+        const SUM: [u8; 4] = {
+            let [tag, length] = Tlv::encoded_header(TlvType::Sum, 2);
+            [tag, length, 3, 4]
+        };
+
+        assert_eq!(&PING[..], &*Tlv::new(TlvType::Ping, &[]).unwrap().encode());
+        assert_eq!(
+            Operation::try_from(&SUM[..]).unwrap(),
+            Operation::Sum((3, 4).into())
+        );
+    }
+
+    #[test]
+    fn tlv_exact() {
+        let bytes = [1, 2, 3, 4, 0];
+        let tlv = Tlv::try_from_exact(&bytes[..4]).unwrap();
+        assert_eq!(tlv.data, &[3, 4]);
+
+        assert!(matches!(
+            Tlv::try_from_exact(&bytes),
+            Err(TlvError::WrongFormat)
+        ));
+        assert!(Tlv::try_from(&bytes[..]).is_ok());
+    }
+
+    #[test]
+    fn tlv_well_formed() {
+        let tlv: Tlv = (&[1u8, 2, 127, 255][..]).try_into().unwrap();
+        assert!(tlv.is_well_formed());
+        let tlv: Tlv = (&[11u8, 3, b'a', b'b', b'c'][..]).try_into().unwrap();
+        assert!(tlv.is_well_formed());
+    }
+
+    #[test]
+    fn tlv_malformed() {
+        let tlv: Tlv = (&[1u8, 1, 127][..]).try_into().unwrap();
+        assert!(!tlv.is_well_formed());
+    }
+
+    #[test]
+    fn tlv_write_to() {
+        let mut written = vec![];
+        let tlv = Tlv::new(TlvType::Invalid, b"abc").unwrap();
+        tlv.write_to(&mut written).unwrap();
+        assert_eq!(written[..], tlv.encode()[..]);
+    }
+
+    #[test]
+    fn tlv_operations() {
+        let buffer = [1u8, 2, 3, 4, 0, 0, 10, 1, 5, 6, 1, 5];
+        let operations: Vec<_> = TlvIterator::process(&buffer).operations().collect();
+        assert_eq!(operations.len(), 3);
+        assert_eq!(
+            operations[0].as_ref().unwrap(),
+            &Operation::Sum((3, 4).into())
+        );
+        assert!(operations[1].is_err());
+
+        let valid: Vec<_> = TlvIterator::process(&buffer)
+            .operations()
+            .flatten()
+            .collect();
+        assert_eq!(
+            valid,
+            [Operation::Sum((3, 4).into()), Operation::Fact(5.into())]
+        );
+    }
+
+    #[test]
+    fn tlv_iter_limit() {
+        let buffer = [1u8, 2, 3, 4, 6, 1, 5, 6, 1, 6, 1u8, 2, 5, 6, 6, 1, 7];
+        let mut iterator = TlvIterator::with_limit(&buffer, 3);
+        assert_eq!(iterator.by_ref().count(), 3);
+        assert!(iterator.peek().is_none());
+        assert!(iterator.next().is_none());
+        assert_eq!(iterator.remaining(), [1u8, 2, 5, 6, 6, 1, 7]);
+
+        let mut iterator = TlvIterator::process(&buffer);
+        assert_eq!(iterator.by_ref().count(), 5);
+        assert!(iterator.remaining().is_empty());
+    }
+
+    #[test]
+    fn tlv_fixed() {
+        let tlv: Tlv = (&[1u8, 2, 127, 255][..]).try_into().unwrap();
+        assert_eq!(tlv.fixed::<2>().unwrap(), [127, 255]);
+    }
+
+    #[test]
+    fn tlv_fixed_wrong_length() {
+        let tlv: Tlv = (&[1u8, 2, 127, 255][..]).try_into().unwrap();
+        assert!(matches!(tlv.fixed::<1>(), Err(TlvError::WrongFormat)));
+        assert!(matches!(tlv.fixed::<8>(), Err(TlvError::WrongFormat)));
+    }
+
+    #[test]
+    fn build_frame() {
+        let mut builder = TlvBuilder::new();
+        builder
+            .push(TlvType::Sum, &[1, 2])
+            .unwrap()
+            .push(TlvType::Fact, &[5])
+            .unwrap();
+
+        assert_eq!(builder.encode()[..], [1u8, 2, 1, 2, 6, 1, 5]);
+        assert_eq!(
+            builder.wrap(TlvType::Answer).unwrap()[..],
+            [10u8, 7, 1, 2, 1, 2, 6, 1, 5]
+        );
+    }
+
+    #[test]
+    fn build_frame_too_long() {
+        let mut builder = TlvBuilder::new();
+        builder.push(TlvType::Invalid, &[0; 200]).unwrap();
+        builder.push(TlvType::Invalid, &[0; 200]).unwrap();
+        assert!(matches!(
+            builder.wrap(TlvType::Answer),
+            Err(TlvError::ExcessiveLength(_))
+        ));
+        assert!(matches!(
+            builder.push(TlvType::Invalid, &[0; 256]),
+            Err(TlvError::ExcessiveLength(_))
+        ));
+    }
+
+    #[test]
+    fn peek_tlv_iter() {
+        let mut iterator = TlvIterator::process(&[1u8, 2, 0, 0, 6, 1, 5]);
+
+        assert_eq!(iterator.peek().unwrap().tag, TlvType::Sum);
+        assert_eq!(iterator.peek(), iterator.next());
+        assert_eq!(iterator.peek().unwrap().tag, TlvType::Fact);
+        assert_eq!(iterator.peek(), iterator.next());
+        assert_eq!(iterator.peek(), None);
+        assert_eq!(iterator.next(), None);
+    }
+
+    #[test]
+    fn tlv_iter_position() {
+        let buf = [1u8, 2, 0, 0, 6, 1, 5, 16];
+        let mut iterator = TlvIterator::process(&buf);
+
+        assert_eq!(iterator.position(), 0);
+        iterator.next();
+        assert_eq!(iterator.position(), 4);
+        iterator.next();
+        assert_eq!(iterator.position(), 7);
+        assert_eq!(iterator.next(), None);
+        assert_eq!(iterator.position(), 7);
+    }
+
+    #[test]
+    fn dump_sum() {
+        assert_eq!(
+            hexdump(&Operation::Sum((1, 2).into()).encode()),
+            "01 02 01 02"
+        );
+        assert_eq!(hexdump(&[]), "");
+    }
+
+    #[test]
+    fn dump_annotated() {
+        assert_eq!(
+            hexdump_annotated(&[1, 2, 1, 2, 6, 1, 255, 42]),
+            "Sum[01 02] 01 02 Fact[06 01] ff 2a"
+        );
+    }
+
+    #[test]
+    fn tlv_round_trip_property() {
+        let tags: Vec<TlvType> = (0..=255u8).filter_map(|t| t.try_into().ok()).collect();
+        let mut rng = XorShift::new(0x5eed);
+
+        for _ in 0..10_000 {
+            let tag = tags[rng.next_u64() as usize % tags.len()];
+            let data: Vec<u8> = (0..rng.next_u64() % 256)
+                .map(|_| rng.next_u64() as u8)
+                .collect();
+
+            let encoded = Tlv::new(tag, &data).unwrap().encode();
+            assert_eq!(encoded.len(), data.len() + 2);
+            assert_eq!(
+                Tlv::try_from(&encoded[..]).unwrap(),
+                Tlv::new(tag, &data).unwrap()
+            );
+        }
+    }
+
+    /// Random input for the parser, in the spirit of the fuzz target under
+    /// `fuzz/`, but cheap enough to run with the rest of the tests
+    #[test]
+    fn tlv_parser_garbage() {
+        let tags: Vec<u8> = (0..=255u8)
+            .filter(|&t| TlvType::try_from(t).is_ok())
+            .collect();
+        let mut rng = XorShift::new(0xf00d);
+
+        for _ in 0..10_000 {
+            let mut bytes: Vec<u8> = (0..rng.next_u64() % 64)
+                .map(|_| rng.next_u64() as u8)
+                .collect();
+            // Known tags go deeper into the parser
+            for byte in bytes.iter_mut().step_by(3) {
+                if rng.next_u64() & 1 == 0 {
+                    *byte = tags[rng.next_u64() as usize % tags.len()];
+                }
+            }
+
+            if let Ok(tlv) = Tlv::try_from(&bytes[..]) {
+                assert_eq!(tlv.data.len(), tlv.length as usize);
+                let _ = Operation::try_from(tlv);
+            }
+            let mut consumed = 0;
+            for tlv in TlvIterator::process(&bytes) {
+                consumed += 2 + tlv.data.len();
+                assert!(consumed <= bytes.len());
+                let _ = Answer::try_from(tlv);
+            }
+            let _ = hexdump_annotated(&bytes);
+        }
+    }
+
+    #[test]
+    fn parse_tlv_long_lengths() {
+        for length in [254u8, 255] {
+            let mut bytes = vec![11u8, length];
+            bytes.extend(std::iter::repeat_n(b'x', length.into()));
+
+            let tlv = Tlv::try_from(&bytes[..]).unwrap();
+            assert_eq!(tlv.length, length);
+            assert_eq!(tlv.data.len(), length as usize);
+
+            assert!(matches!(
+                Tlv::try_from(&bytes[..bytes.len() - 1]),
+                Err(TlvError::WrongFormat)
+            ));
+            assert!(matches!(
+                Tlv::try_from(&bytes[..2]),
+                Err(TlvError::WrongFormat)
+            ));
+        }
+    }
+
     #[test]
     fn parse_tlv_iter() {
         let mut iterator = TlvIterator::process(&[
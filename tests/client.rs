@@ -0,0 +1,303 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    process::{Command, Output, Stdio},
+    thread,
+    time::Duration,
+};
+
+use tcpmt::{Answer, AnswerOrder, Tlv, TlvType, PROTOCOL_VERSION};
+
+/// Runs the client against `port` feeding it `input`
+fn run_client(port: u16, args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tcpmtcli"))
+        .args(["127.0.0.1", &port.to_string()])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Accepts a connection and announces the protocol version, like the server
+fn accept(listener: &TcpListener) -> TcpStream {
+    accept_with_version(listener, PROTOCOL_VERSION)
+}
+
+fn accept_with_version(listener: &TcpListener, version: u8) -> TcpStream {
+    let (mut stream, _) = listener.accept().unwrap();
+    stream
+        .write_all(&Tlv::new(TlvType::Version, &[version]).unwrap().encode())
+        .unwrap();
+    stream
+}
+
+/// Answers a single operation with `acc` as the accumulator
+fn answer_one(stream: &mut TcpStream, acc: i64) {
+    let mut buffer = [0u8; 2048];
+    assert!(stream.read(&mut buffer).unwrap() > 0);
+    stream
+        .write_all(
+            &Answer::from((acc, None))
+                .encode(AnswerOrder::MessageFirst)
+                .unwrap(),
+        )
+        .unwrap();
+}
+
+#[test]
+fn reconnect() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        // The first connection is closed after one operation
+        let mut stream = accept(&listener);
+        answer_one(&mut stream, 7);
+        drop(stream);
+
+        let mut stream = accept(&listener);
+        answer_one(&mut stream, 42);
+    });
+
+    let output = run_client(port, &["--retries", "2"], "3+4\n1+1\n2+2\nQUIT\n");
+    server.join().unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stdout.contains("Accumulator: 7"));
+    assert!(stdout.contains("Accumulator: 42"));
+    assert!(stderr.contains("Reconnected"));
+}
+
+#[test]
+fn split_answer() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let mut stream = accept(&listener);
+        stream.set_nodelay(true).unwrap();
+        let mut buffer = [0u8; 2048];
+        assert!(stream.read(&mut buffer).unwrap() > 0);
+        let answer = Answer::from((-3, Some("Something went wrong".into())));
+        for byte in answer.encode(AnswerOrder::MessageFirst).unwrap().iter() {
+            stream.write_all(&[*byte]).unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+    });
+
+    let output = run_client(port, &[], "3-6\nQUIT\n");
+    server.join().unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Accumulator: -3 Error: Something went wrong"));
+}
+
+#[test]
+fn keepalive() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let mut stream = accept(&listener);
+        let mut ping = [0u8; 2];
+        stream.read_exact(&mut ping).unwrap();
+        assert_eq!(ping, [30u8, 0]);
+        stream.write_all(&[31u8, 0]).unwrap();
+    });
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tcpmtcli"))
+        .args(["127.0.0.1", &port.to_string(), "--keepalive", "1"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    server.join().unwrap();
+    child.stdin.take().unwrap().write_all(b"QUIT\n").unwrap();
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn no_retries() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let mut stream = accept(&listener);
+        answer_one(&mut stream, 7);
+    });
+
+    let output = run_client(port, &[], "3+4\n1+1\nQUIT\n");
+    server.join().unwrap();
+
+    assert!(!output.status.success());
+}
+
+#[test]
+fn newer_server_version() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let mut stream = accept_with_version(&listener, PROTOCOL_VERSION + 1);
+        answer_one(&mut stream, 7);
+    });
+
+    let output = run_client(port, &[], "3+4\nQUIT\n");
+    server.join().unwrap();
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("newer than the supported"));
+}
+
+#[test]
+fn server_without_version() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        // Older servers only speak when asked
+        let (mut stream, _) = listener.accept().unwrap();
+        answer_one(&mut stream, 7);
+    });
+
+    let output = run_client(port, &[], "3+4\nQUIT\n");
+    server.join().unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Accumulator: 7\n"));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("did not announce its protocol version"));
+}
+
+#[test]
+fn two_answers_together() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let mut stream = accept(&listener);
+        let mut buffer = [0u8; 2048];
+        assert!(stream.read(&mut buffer).unwrap() > 0);
+        let answers = [
+            Answer::from((7, None))
+                .encode(AnswerOrder::MessageFirst)
+                .unwrap(),
+            Answer::from((17, None))
+                .encode(AnswerOrder::MessageFirst)
+                .unwrap(),
+        ]
+        .concat();
+        stream.write_all(&answers).unwrap();
+    });
+
+    let output = run_client(port, &[], "3+4\nQUIT\n");
+    server.join().unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Accumulator: 7\n"));
+    assert!(stdout.contains("Accumulator: 17\n"));
+}
+
+#[test]
+fn evaluate_result() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        // Like the answer to an Evaluate TLV, which leaves the accumulator as is
+        let mut stream = accept(&listener);
+        let mut buffer = [0u8; 2048];
+        assert!(stream.read(&mut buffer).unwrap() > 0);
+        stream
+            .write_all(
+                &Answer::ok(100)
+                    .with_result(17)
+                    .encode(AnswerOrder::MessageFirst)
+                    .unwrap(),
+            )
+            .unwrap();
+    });
+
+    let output = run_client(port, &[], "3+4\nQUIT\n");
+    server.join().unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Result: 17 Accumulator: 100\n"));
+}
+
+/// Answers every operation until the client closes the connection, returning
+/// all the bytes received
+fn answer_all(listener: TcpListener) -> Vec<u8> {
+    let mut stream = accept(&listener);
+    let mut received = vec![];
+    let mut buffer = [0u8; 2048];
+    loop {
+        match stream.read(&mut buffer).unwrap() {
+            0 => return received,
+            len => received.extend_from_slice(&buffer[..len]),
+        }
+        stream
+            .write_all(
+                &Answer::from((1, None))
+                    .encode(AnswerOrder::MessageFirst)
+                    .unwrap(),
+            )
+            .unwrap();
+    }
+}
+
+#[test]
+fn record_and_replay() {
+    let capture = std::env::temp_dir().join(format!("tcpmt-{}.cap", std::process::id()));
+    let capture = capture.to_str().unwrap();
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || answer_all(listener));
+    let output = run_client(port, &["--record", capture], "3+4\n1000*2\n5!\nQUIT\n");
+    let recorded = server.join().unwrap();
+    assert!(output.status.success());
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || answer_all(listener));
+    let output = run_client(port, &["--replay", capture], "");
+    let replayed = server.join().unwrap();
+    std::fs::remove_file(capture).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(recorded.len(), 4 + 6 + 3);
+    assert_eq!(replayed, recorded);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.matches("Accumulator: 1").count(), 3);
+}
@@ -0,0 +1,563 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+use tcpmt::{
+    append_checksum, deframe, frame, Answer, Operation, Tlv, TlvIterator, TlvType, PROTOCOL_VERSION,
+};
+
+/// A running server process, killed when dropped
+struct Server {
+    child: Child,
+    port: u16,
+}
+
+/// Lets the OS pick a free port for us
+fn free_port() -> u16 {
+    TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+impl Server {
+    fn spawn(args: &[&str]) -> Self {
+        Self::spawn_with_stdout(args, Stdio::null())
+    }
+
+    fn spawn_with_stdout(args: &[&str], stdout: Stdio) -> Self {
+        let port = free_port();
+        let child = Command::new(env!("CARGO_BIN_EXE_tcpmtser"))
+            .arg(port.to_string())
+            .args(args)
+            .stdout(stdout)
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        Server { child, port }
+    }
+
+    /// Connects and skips the protocol version announced by the server
+    fn connect(&self, ip: IpAddr) -> TcpStream {
+        let mut stream = self.open(ip);
+        let mut version = [0u8; 3];
+        stream.read_exact(&mut version).unwrap();
+        assert_eq!(version[0], u8::from(TlvType::Version));
+        stream
+    }
+
+    fn open(&self, ip: IpAddr) -> TcpStream {
+        self.open_port(ip, self.port)
+    }
+
+    fn open_port(&self, ip: IpAddr, port: u16) -> TcpStream {
+        for _ in 0..50 {
+            if let Ok(stream) = TcpStream::connect(SocketAddr::from((ip, port))) {
+                return stream;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        panic!("Could not connect to the server");
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn calculate(stream: &mut TcpStream, operation: Operation) -> Answer {
+    let mut buffer = [0u8; 2048];
+    stream.write_all(&operation.encode()).unwrap();
+    let len = stream.read(&mut buffer).unwrap();
+    Tlv::try_from(&buffer[..len]).unwrap().try_into().unwrap()
+}
+
+#[test]
+fn ipv4_only() {
+    let server = Server::spawn(&["--ipv4-only"]);
+    let mut stream = server.connect(Ipv4Addr::LOCALHOST.into());
+
+    assert_eq!(
+        calculate(&mut stream, Operation::Sum((3, 4).into())).acc.0,
+        7
+    );
+    assert_eq!(
+        calculate(&mut stream, Operation::Mul((2, 5).into())).acc.0,
+        17
+    );
+}
+
+#[test]
+fn version_first() {
+    let server = Server::spawn(&[]);
+    let mut stream = server.open(Ipv4Addr::LOCALHOST.into());
+
+    let mut version = [0u8; 3];
+    stream.read_exact(&mut version).unwrap();
+    assert_eq!(version, [40u8, 1, PROTOCOL_VERSION]);
+}
+
+#[test]
+fn max_connections() {
+    let server = Server::spawn(&["--max-connections", "2"]);
+    let mut streams: Vec<_> = (0..2)
+        .map(|_| server.connect(Ipv4Addr::LOCALHOST.into()))
+        .collect();
+    for stream in streams.iter_mut() {
+        assert_eq!(calculate(stream, Operation::Sum((3, 4).into())).acc.0, 7);
+    }
+
+    let mut refused = server.open(Ipv4Addr::LOCALHOST.into());
+    let _ = refused.write_all(&Operation::Sum((3, 4).into()).encode());
+    assert!(matches!(refused.read(&mut [0u8; 2048]), Ok(0) | Err(_)));
+
+    // Once a connection finishes there is room for a new one
+    drop(streams.pop());
+    thread::sleep(Duration::from_millis(200));
+    let mut stream = server.connect(Ipv4Addr::LOCALHOST.into());
+    assert_eq!(
+        calculate(&mut stream, Operation::Sum((3, 4).into())).acc.0,
+        7
+    );
+}
+
+#[test]
+fn sticky() {
+    let server = Server::spawn(&["--sticky"]);
+    let mut stream = server.connect(Ipv4Addr::LOCALHOST.into());
+    assert_eq!(
+        calculate(&mut stream, Operation::Sum((3, 4).into())).acc.0,
+        7
+    );
+    drop(stream);
+
+    let mut stream = server.connect(Ipv4Addr::LOCALHOST.into());
+    assert_eq!(
+        calculate(&mut stream, Operation::Sum((1, 1).into())).acc.0,
+        9
+    );
+}
+
+#[test]
+fn not_sticky() {
+    let server = Server::spawn(&[]);
+    let mut stream = server.connect(Ipv4Addr::LOCALHOST.into());
+    assert_eq!(
+        calculate(&mut stream, Operation::Sum((3, 4).into())).acc.0,
+        7
+    );
+    drop(stream);
+
+    let mut stream = server.connect(Ipv4Addr::LOCALHOST.into());
+    assert_eq!(
+        calculate(&mut stream, Operation::Sum((1, 1).into())).acc.0,
+        2
+    );
+}
+
+#[test]
+fn initial_acc() {
+    let server = Server::spawn(&["--initial-acc", "-100", "--sticky"]);
+    let mut stream = server.connect(Ipv4Addr::LOCALHOST.into());
+    assert_eq!(
+        calculate(&mut stream, Operation::Sum((3, 4).into())).acc.0,
+        -93
+    );
+    drop(stream);
+
+    // The initial value is only for new clients
+    let mut stream = server.connect(Ipv4Addr::LOCALHOST.into());
+    assert_eq!(
+        calculate(&mut stream, Operation::Sum((1, 1).into())).acc.0,
+        -91
+    );
+}
+
+#[test]
+fn require_checksum() {
+    let server = Server::spawn(&["--require-checksum", "--framed"]);
+    let mut stream = server.open(Ipv4Addr::LOCALHOST.into());
+    deframe(&mut stream).unwrap();
+
+    let request = [
+        Operation::Sum((3, 4).into()).encode(),
+        Operation::Mul((2, 5).into()).encode(),
+    ]
+    .concat();
+    stream
+        .write_all(&frame(&append_checksum(&request)))
+        .unwrap();
+    let reply = deframe(&mut stream).unwrap();
+    let answers: Vec<_> = TlvIterator::process(&reply)
+        .map(|tlv| Answer::try_from(tlv).unwrap())
+        .collect();
+    assert_eq!(answers, [Answer::ok(7), Answer::ok(17)]);
+
+    stream.write_all(&frame(&request)).unwrap();
+    let reply = deframe(&mut stream).unwrap();
+    let answer = Answer::try_from(Tlv::try_from(&reply[..]).unwrap()).unwrap();
+    assert_eq!(answer.acc.0, 17);
+    assert!(answer.message.is_some());
+}
+
+#[test]
+fn many_operations() {
+    let server = Server::spawn(&[]);
+    let mut stream = server.connect(Ipv4Addr::LOCALHOST.into());
+
+    let request: Vec<u8> = (0..1000)
+        .flat_map(|_| Operation::Sum((1, 1).into()).encode().into_vec())
+        .collect();
+    stream.write_all(&request).unwrap();
+
+    // Every answer has a single Numi64 TLV
+    let mut answers = vec![0u8; 1000 * 12];
+    stream.read_exact(&mut answers).unwrap();
+    for (i, answer) in answers.chunks(12).enumerate() {
+        let answer = Answer::try_from(Tlv::try_from(answer).unwrap()).unwrap();
+        assert_eq!(answer.acc.0, 2 * (i as i64 + 1));
+    }
+}
+
+#[test]
+fn small_buffer() {
+    let server = Server::spawn(&["--buffer-size", "64"]);
+    let mut stream = server.connect(Ipv4Addr::LOCALHOST.into());
+
+    // TLVs of 4 and 3 bytes end up split between reads of 64 bytes
+    let request: Vec<u8> = [Operation::Sum((1, 1).into()), Operation::Fact(3.into())]
+        .iter()
+        .cycle()
+        .take(100)
+        .flat_map(|operation| operation.clone().encode().into_vec())
+        .collect();
+    stream.write_all(&request).unwrap();
+
+    let mut answers = vec![0u8; 100 * 12];
+    stream.read_exact(&mut answers).unwrap();
+    let mut expected = 0;
+    for (answer, result) in answers.chunks(12).zip([2, 6].iter().cycle()) {
+        expected += result;
+        let answer = Answer::try_from(Tlv::try_from(answer).unwrap()).unwrap();
+        assert_eq!(answer.acc.0, expected);
+    }
+}
+
+#[test]
+fn split_operation() {
+    let server = Server::spawn(&[]);
+    let mut stream = server.connect(Ipv4Addr::LOCALHOST.into());
+    stream.set_nodelay(true).unwrap();
+
+    let request = Operation::Sum((3, 4).into()).encode();
+    stream.write_all(&request[..3]).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    stream.write_all(&request[3..]).unwrap();
+
+    let mut buffer = [0u8; 2048];
+    let len = stream.read(&mut buffer).unwrap();
+    let answer = Answer::try_from(Tlv::try_from(&buffer[..len]).unwrap()).unwrap();
+    assert_eq!(answer.acc.0, 7);
+}
+
+#[test]
+fn ping() {
+    let server = Server::spawn(&[]);
+    let mut stream = server.connect(Ipv4Addr::LOCALHOST.into());
+    let mut buffer = [0u8; 2048];
+
+    assert_eq!(
+        calculate(&mut stream, Operation::Sum((3, 4).into())).acc.0,
+        7
+    );
+    stream
+        .write_all(&Tlv::new(TlvType::Ping, &[]).unwrap().encode())
+        .unwrap();
+    let len = stream.read(&mut buffer).unwrap();
+    assert_eq!(buffer[..len], [31u8, 0]);
+    assert_eq!(
+        calculate(&mut stream, Operation::Sum((1, 1).into())).acc.0,
+        9
+    );
+}
+
+#[test]
+fn echo() {
+    let server = Server::spawn(&["--echo"]);
+    let mut stream = server.connect(Ipv4Addr::LOCALHOST.into());
+
+    for _ in 0..2 {
+        let answer = calculate(&mut stream, Operation::Sum((127, -1).into()));
+        assert_eq!(answer.acc.0, 0);
+        assert_eq!(answer.message.unwrap().to_string(), "127+-1");
+    }
+}
+
+#[test]
+fn delta() {
+    let server = Server::spawn(&["--delta"]);
+    let mut stream = server.connect(Ipv4Addr::LOCALHOST.into());
+
+    let answer = calculate(&mut stream, Operation::Sum((3, 4).into()));
+    assert_eq!((answer.acc.0, answer.delta.unwrap().0), (7, 7));
+    let answer = calculate(&mut stream, Operation::Mul((-2, 5).into()));
+    assert_eq!((answer.acc.0, answer.delta.unwrap().0), (-3, -10));
+    let answer = calculate(&mut stream, Operation::Div((1, 0).into()));
+    assert_eq!(answer.acc.0, -3);
+    assert!(answer.delta.is_none());
+}
+
+#[test]
+fn evaluate_delta() {
+    let server = Server::spawn(&["--delta", "--initial-acc", "100"]);
+    let mut stream = server.connect(Ipv4Addr::LOCALHOST.into());
+
+    let operations = [
+        Operation::Sum((3, 4).into()).encode(),
+        Operation::Mul((5, 2).into()).encode(),
+    ]
+    .concat();
+    let mut buffer = [0u8; 2048];
+    stream
+        .write_all(&Tlv::new(TlvType::Evaluate, &operations).unwrap().encode())
+        .unwrap();
+    let len = stream.read(&mut buffer).unwrap();
+    let answer = Answer::try_from(Tlv::try_from(&buffer[..len]).unwrap()).unwrap();
+    assert_eq!((answer.acc.0, answer.result.unwrap().0), (100, 17));
+    assert!(answer.delta.is_none());
+
+    let answer = calculate(&mut stream, Operation::Sum((1, 1).into()));
+    assert_eq!((answer.acc.0, answer.delta.unwrap().0), (102, 2));
+    assert!(answer.result.is_none());
+}
+
+#[test]
+fn bind_address() {
+    let server = Server::spawn(&["--bind", "127.0.0.1"]);
+    let mut stream = server.connect(Ipv4Addr::LOCALHOST.into());
+
+    assert_eq!(
+        calculate(&mut stream, Operation::Sum((3, 4).into())).acc.0,
+        7
+    );
+}
+
+#[test]
+fn bind_address_wrong_family() {
+    let status = Command::new(env!("CARGO_BIN_EXE_tcpmtser"))
+        .args(["1024", "--ipv4-only", "--bind", "::1"])
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
+
+#[test]
+fn text_port() {
+    let port = free_port();
+    let server = Server::spawn(&["--text-port", &port.to_string()]);
+    let mut stream = server.open_port(Ipv4Addr::LOCALHOST.into(), port);
+
+    stream.write_all(b"3+4\n5 * 2\n\n3/0\n").unwrap();
+    let mut lines = BufReader::new(stream).lines();
+    assert_eq!(lines.next().unwrap().unwrap(), "7");
+    assert_eq!(lines.next().unwrap().unwrap(), "17");
+    assert!(lines.next().unwrap().unwrap().starts_with("17 Error:"));
+}
+
+#[test]
+fn framed() {
+    let server = Server::spawn(&["--framed"]);
+    let mut stream = server.open(Ipv4Addr::LOCALHOST.into());
+    let version = deframe(&mut stream).unwrap();
+    assert_eq!(version, [40u8, 1, PROTOCOL_VERSION]);
+
+    let request = [
+        Operation::Sum((3, 4).into()).encode(),
+        Operation::Mul((2, 5).into()).encode(),
+    ]
+    .concat();
+    stream.write_all(&frame(&request)).unwrap();
+    let reply = deframe(&mut stream).unwrap();
+    let accs: Vec<_> = TlvIterator::process(&reply)
+        .map(|tlv| Answer::try_from(tlv).unwrap().acc.0)
+        .collect();
+    assert_eq!(accs, [7, 17]);
+}
+
+#[test]
+fn long_batch() {
+    let server = Server::spawn(&["--framed", "--batch"]);
+    let mut stream = server.open(Ipv4Addr::LOCALHOST.into());
+    deframe(&mut stream).unwrap();
+
+    let request: Vec<u8> = (0..30)
+        .flat_map(|_| Operation::Sum((1, 1).into()).encode().into_vec())
+        .collect();
+    stream.write_all(&frame(&request)).unwrap();
+    let reply = deframe(&mut stream).unwrap();
+    let answers: Vec<_> = TlvIterator::process(&reply)
+        .map(|tlv| Answer::try_from(tlv).unwrap())
+        .collect();
+    assert_eq!(answers.len(), 2);
+    let accs: Vec<_> = answers
+        .iter()
+        .flat_map(Answer::values)
+        .map(|v| v.0)
+        .collect();
+    assert_eq!(accs, (1..=30).map(|i| 2 * i).collect::<Vec<_>>());
+}
+
+/// Number of threads of the process `pid`
+#[cfg(target_os = "linux")]
+fn threads(pid: u32) -> usize {
+    std::fs::read_dir(format!("/proc/{pid}/task"))
+        .unwrap()
+        .count()
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn no_thread_leak() {
+    let server = Server::spawn(&[]);
+    let pid = server.child.id();
+
+    for _ in 0..10_000 {
+        drop(server.connect(Ipv4Addr::LOCALHOST.into()));
+    }
+
+    // Only the main thread and the one waiting for signals must remain once
+    // every connection is closed
+    for _ in 0..100 {
+        if threads(pid) == 2 {
+            return;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("{} threads still running", threads(pid));
+}
+
+#[test]
+fn op_result() {
+    let server = Server::spawn(&["--op-result", "--initial-acc", "100"]);
+    let mut stream = server.connect(Ipv4Addr::LOCALHOST.into());
+    let answer = calculate(&mut stream, Operation::Mul((3, 4).into()));
+    assert_eq!(answer.acc.0, 112);
+    assert_eq!(answer.result.unwrap().0, 12);
+
+    let server = Server::spawn(&[]);
+    let mut stream = server.connect(Ipv4Addr::LOCALHOST.into());
+    let answer = calculate(&mut stream, Operation::Mul((3, 4).into()));
+    assert_eq!(answer.result, None);
+}
+
+#[test]
+fn delay() {
+    let server = Server::spawn(&["--delay", "0", "--jitter", "5", "--seed", "7"]);
+    let mut stream = server.connect(Ipv4Addr::LOCALHOST.into());
+    assert_eq!(
+        calculate(&mut stream, Operation::Sum((3, 4).into())).acc.0,
+        7
+    );
+    assert_eq!(
+        calculate(&mut stream, Operation::Mul((2, 5).into())).acc.0,
+        17
+    );
+}
+
+#[test]
+fn stats() {
+    let server = Server::spawn(&[]);
+    for _ in 0..2 {
+        let mut stream = server.connect(Ipv4Addr::LOCALHOST.into());
+        assert_eq!(
+            calculate(&mut stream, Operation::Sum((3, 4).into())).acc.0,
+            7
+        );
+    }
+
+    let mut stream = server.connect(Ipv4Addr::LOCALHOST.into());
+    let mut buffer = [0u8; 2048];
+    stream
+        .write_all(&Tlv::new(TlvType::Stats, &[]).unwrap().encode())
+        .unwrap();
+    let len = stream.read(&mut buffer).unwrap();
+    let reply = Tlv::try_from(&buffer[..len]).unwrap();
+    assert_eq!(reply.tag, TlvType::Stats);
+    let mut children = reply.children();
+    let total = children.next().unwrap();
+    assert_eq!(total.tag, TlvType::Numi64);
+    assert_eq!(u64::from_be_bytes(total.fixed::<8>().unwrap()), 2);
+    let counts: Vec<_> = children
+        .map(|tlv| {
+            assert!(tlv.is_well_formed());
+            let [tag, count @ ..] = tlv.fixed::<9>().unwrap();
+            (tag, u64::from_be_bytes(count))
+        })
+        .collect();
+    assert_eq!(counts, [(u8::from(TlvType::Sum), 2)]);
+}
+
+#[cfg(unix)]
+#[test]
+fn stats_on_shutdown() {
+    let text_port = free_port();
+    let mut server =
+        Server::spawn_with_stdout(&["--text-port", &text_port.to_string()], Stdio::piped());
+    let mut stream = server.connect(Ipv4Addr::LOCALHOST.into());
+    assert_eq!(
+        calculate(&mut stream, Operation::Sum((3, 4).into())).acc.0,
+        7
+    );
+    let mut stream = server.open_port(Ipv4Addr::LOCALHOST.into(), text_port);
+    stream.write_all(b"1+1\n").unwrap();
+    let mut lines = BufReader::new(stream).lines();
+    assert_eq!(lines.next().unwrap().unwrap(), "2");
+
+    // SAFETY: the child process is still running, as it has not been waited for
+    assert_eq!(
+        unsafe { libc::kill(server.child.id() as i32, libc::SIGTERM) },
+        0
+    );
+    assert!(server.child.wait().unwrap().success());
+    let mut output = String::new();
+    server
+        .child
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut output)
+        .unwrap();
+    assert!(output.contains("2 operations computed. Sum 2"), "{output}");
+}